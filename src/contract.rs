@@ -1,162 +1,3546 @@
-use schemars::JsonSchema;
+use serde::de::IgnoredAny;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
 
 use cosmwasm_std::{
-    from_slice, to_binary, to_vec, Binary, Deps, DepsMut, Env, MessageInfo, Order,
-    QueryResponse, Response, StdResult, Storage,
+    coins, from_slice, to_binary, to_vec, Addr, BankMsg, Binary, BlockInfo, Coin,
+    ContractInfoResponse, ContractResult, Decimal, Deps, DepsMut, Empty, Env, Event, MessageInfo,
+    Order, Pair, QueryRequest, QueryResponse, Response, StdError, StdResult, Storage, SystemResult,
+    Timestamp, Uint128, WasmMsg, WasmQuery,
+};
+use cw2::{set_contract_version, ContractVersion, CONTRACT};
+use cw_storage_plus::{Bound, Map, PrimaryKey, U64Key};
+use cw_utils::{must_pay, nonpayable, one_coin, PaymentError};
+
+use crate::error::ContractError;
+use crate::msg::{
+    AdminOpSignDoc, BatchResponse, Bid, BlockMetricsEntry, BlockMetricsResponse, Bucket,
+    BucketedSumResponse, CallerRestriction, CircuitBreakerResponse, CouncilConfig,
+    CountByValueResponse, CountResponse, DataEncoding, DepthAt, DepthHistoryResponse,
+    DistributionResponse, Escrow, ExecuteMsg, FailedDeliveriesResponse, InstantiateMsg, Item,
+    ItemRef, ItemResponse, ItemsResponse, IterateResponse, KeyLayoutResponse, Limits,
+    ListBucketsResponse, ListChannelsResponse, ListResponse, ListStacksResponse, LotteryConfig,
+    LotteryResponse, MigrateMsg, MovingAverageResponse, MultiResponse, NoisCallback, OpRecord,
+    OpsFeedResponse, PageRequest, PageResponse, Payload, PeekResponse, PercentileResponse,
+    PopAuctionConfig, PopAuctionResponse, PopManyResponse, Proposal, ProposalResponse,
+    QuarantinedItem, QuarantinedItemsResponse, QueryMsg, SealedItem, StackInfo, StatusResponse,
+    SudoMsg, SumResponse, TreasuryResponse, ValueCount, WorkClaim,
 };
+use crate::nois::{decode_randomness, int_below, ProxyExecuteMsg};
+use crate::proto::encode_any;
+use crate::rng::Xoshiro256;
+use crate::state::{
+    decrement_value_count, increment_value_count, item_index, item_storage_key, items_prefix,
+    record_block_metrics, record_depth_snapshot, record_named_op, record_op, u64_from_key,
+    update_stats, value_key, BlockActivity, BlockMetrics, Config, Council, ItemBounds, Member,
+    NamedStack, RandomJob, RecentValues, Stats, ADMIN_NONCE, BLOCK_ACTIVITY, BLOCK_METRICS,
+    BLOCK_METRICS_LEN, CHANNELS, CLEARING, CONFIG, CONFIG_KEY, COUNCIL, DEPTH, DEPTH_HISTORY,
+    DEPTH_HISTORY_LEN, DEPTH_KEY, FAILED_DELIVERIES, FAILED_DELIVERIES_LEN, HEIGHT_INDEX,
+    ITEM_BOUNDS, KEY_LAYOUT_VERSION, MAX_DEPTH_SNAPSHOTS, NAMED_ITEMS, OP_LOG, OP_SEQ_KEY, PAUSED,
+    POP_BID, POT, PROPOSALS, PROPOSAL_COUNT, QUARANTINED, RANDOM_JOBS, RECENT_VALUES, SIGNED_OP,
+    STACKS, STATS, TIME_INDEX, VALUE_COUNTS, VOTES,
+};
+
+const CONTRACT_NAME: &str = concat!("crates.io:", env!("CARGO_PKG_NAME"));
+const CONTRACT_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+const DEFAULT_MOVING_AVERAGE_WINDOW: u32 = 32;
+const MAX_MOVING_AVERAGE_WINDOW: u32 = 256;
+
+// Stores the sender (or the contract itself, if a council is given) as owner,
+// along with the circuit breaker threshold
+pub fn instantiate(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    msg: InstantiateMsg,
+) -> Result<Response, ContractError> {
+    println!("-- Instantiate --");
+    reject_funds(&info)?;
+    if let Some(capacity) = msg.capacity {
+        if capacity == 0 || capacity > MAX_CAPACITY {
+            return Err(ContractError::InvalidConfig {
+                msg: format!("Capacity must be between 1 and {}", MAX_CAPACITY),
+            });
+        }
+    }
+    let nois_proxy = msg
+        .nois_proxy
+        .map(|addr| deps.api.addr_validate(&addr))
+        .transpose()?;
+    if let Some(lottery) = &msg.lottery {
+        if nois_proxy.is_none() || msg.hidden_values == Some(true) {
+            return Err(ContractError::InvalidConfig {
+                msg: "Lottery mode needs a nois proxy and visible values".to_string(),
+            });
+        }
+        if lottery.ticket_price.is_zero() {
+            return Err(ContractError::InvalidConfig {
+                msg: "Ticket price must not be zero".to_string(),
+            });
+        }
+        POT.save(deps.storage, &Uint128::zero())?;
+    }
+    if let Some(timeout) = msg.claim_timeout {
+        if timeout == 0 {
+            return Err(ContractError::InvalidConfig {
+                msg: "Claim timeout must not be zero".to_string(),
+            });
+        }
+        if msg.hidden_values == Some(true) || msg.lottery.is_some() || msg.pop_auction.is_some() {
+            return Err(ContractError::InvalidConfig {
+                msg: "Work queue mode needs visible values and no lottery or auction".to_string(),
+            });
+        }
+    }
+    // random and hidden values pops would get around the auction
+    if msg.pop_auction.is_some() && (nois_proxy.is_some() || msg.hidden_values == Some(true)) {
+        return Err(ContractError::InvalidConfig {
+            msg: "Pop auction mode can't be combined with random pops or hidden values".to_string(),
+        });
+    }
+    if let Some(auction) = &msg.pop_auction {
+        validate_pop_auction(auction)?;
+    }
+    let owner = match msg.council {
+        Some(council) => {
+            let council = validate_council(deps.as_ref(), council)?;
+            COUNCIL.save(deps.storage, &council)?;
+            PROPOSAL_COUNT.save(deps.storage, &0)?;
+            env.contract.address
+        }
+        None => info.sender,
+    };
+    if msg.clear_chunk_size == Some(0) {
+        return Err(ContractError::InvalidConfig {
+            msg: "Clear chunk size must not be zero".to_string(),
+        });
+    }
+    if msg.pop_type_url.as_deref() == Some("") {
+        return Err(ContractError::InvalidConfig {
+            msg: "Pop type URL must not be empty".to_string(),
+        });
+    }
+    let pushers = validate_allowlist(deps.as_ref(), msg.pushers)?;
+    let poppers = validate_allowlist(deps.as_ref(), msg.poppers)?;
+    let pop_into_targets = validate_allowlist(deps.as_ref(), msg.pop_into_targets)?;
+    if let Some(key) = &msg.cold_admin_key {
+        validate_pubkey(key)?;
+    }
+    let config = Config {
+        owner,
+        max_ops_per_block: msg.max_ops_per_block,
+        push_restriction: msg.push_restriction,
+        hidden_values: msg.hidden_values.unwrap_or(false),
+        capacity: msg.capacity,
+        nois_proxy,
+        lottery: msg.lottery,
+        pop_auction: msg.pop_auction,
+        claim_timeout: msg.claim_timeout,
+        data_encoding: msg.data_encoding.unwrap_or_default(),
+        pop_type_url: msg.pop_type_url,
+        clear_chunk_size: msg.clear_chunk_size,
+        pushers,
+        poppers,
+        pop_into_targets,
+        limits: msg.limits.unwrap_or_default(),
+        cold_admin_key: msg.cold_admin_key,
+    };
+    CONFIG.save(deps.storage, &config)?;
+    PAUSED.save(deps.storage, &false)?;
+
+    let window = msg
+        .moving_average_window
+        .unwrap_or(DEFAULT_MOVING_AVERAGE_WINDOW);
+    if window == 0 || window > MAX_MOVING_AVERAGE_WINDOW {
+        return Err(ContractError::InvalidConfig {
+            msg: format!(
+                "Moving average window must be between 1 and {}",
+                MAX_MOVING_AVERAGE_WINDOW
+            ),
+        });
+    }
+    RECENT_VALUES.save(deps.storage, &RecentValues::new(window))?;
+    ITEM_BOUNDS.save(deps.storage, &ItemBounds::default())?;
+    STATS.save(deps.storage, &Stats::default())?;
+    set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
+    Ok(Response::default())
+}
+
+fn validate_allowlist(deps: Deps, addrs: Option<Vec<String>>) -> StdResult<Option<Vec<Addr>>> {
+    addrs
+        .map(|addrs| {
+            addrs
+                .iter()
+                .map(|addr| deps.api.addr_validate(addr))
+                .collect()
+        })
+        .transpose()
+}
+
+fn validate_council(deps: Deps, council: CouncilConfig) -> Result<Council, ContractError> {
+    let mut members: Vec<Member> = Vec::with_capacity(council.members.len());
+    for member in council.members {
+        let addr = deps.api.addr_validate(&member.addr)?;
+        if member.weight == 0 || members.iter().any(|m| m.addr == addr) {
+            return Err(ContractError::InvalidConfig {
+                msg: format!("Council member {} is listed twice or has no weight", addr),
+            });
+        }
+        members.push(Member {
+            addr,
+            weight: member.weight,
+        });
+    }
+    let total: u64 = members.iter().map(|member| member.weight).sum();
+    if council.threshold == 0 || council.threshold > total {
+        return Err(ContractError::InvalidConfig {
+            msg: format!("Council threshold must be between 1 and {}", total),
+        });
+    }
+    Ok(Council {
+        members,
+        threshold: council.threshold,
+    })
+}
+
+pub fn sudo(deps: DepsMut, env: Env, msg: SudoMsg) -> Result<Response, ContractError> {
+    match msg {
+        SudoMsg::Reducer {} => sudo_reducer(deps, env),
+        SudoMsg::StealHalf { recipient } => sudo_steal_half(deps, env, recipient),
+    }
+}
+
+fn sudo_reducer(deps: DepsMut, env: Env) -> Result<Response, ContractError> {
+    ensure_values_visible(deps.as_ref())?;
+    let entries = active_items(deps.storage, Order::Ascending)
+        .map(|(key, data)| Ok((key, from_slice::<Item>(&data)?)))
+        .collect::<StdResult<Vec<_>>>()?;
+    let mut values: Vec<i32> = entries
+        .iter()
+        .filter_map(|(_, item)| item.numeric())
+        .collect();
+    values.sort_unstable();
+    for (key, mut item) in entries {
+        item.exceeded_by = item
+            .numeric()
+            .map(|value| (values.len() - values.partition_point(|v| *v <= value)) as u32);
+        deps.storage.set(&key, &to_vec(&item)?);
+    }
+    // like a shuffle this touches many items, so mirrors have to reload
+    let seq = record_op(deps.storage, &env.block, "reducer", None, None)?;
+    Ok(Response::new()
+        .add_attribute("action", "reducer")
+        .add_attribute("items", values.len().to_string())
+        .add_attribute("op_seq", seq.to_string()))
+}
+
+fn sudo_steal_half(deps: DepsMut, env: Env, recipient: String) -> Result<Response, ContractError> {
+    let recipient = deps.api.addr_validate(&recipient)?;
+    let mut keys: Vec<Vec<u8>> = active_items(deps.storage, Order::Descending)
+        .map(|(key, _)| key)
+        .collect();
+    keys.truncate(keys.len() / 2);
+    let mut funds = vec![];
+    for key in &keys {
+        if let Some(value) = visible_value(deps.storage, key)? {
+            decrement_value_count(deps.storage, value)?;
+        }
+        let data = remove_item(deps.storage, key)?;
+        if let Some(escrow) = from_slice::<ItemMeta>(&data)?.escrow {
+            for coin in escrow.funds {
+                add_coin(&mut funds, &coin.denom, coin.amount);
+            }
+        }
+    }
+    let stolen = keys.len() as i32;
+    update_depth(deps.storage, env.block.height, -stolen)?;
+    record_block_metrics(deps.storage, env.block.height, 0, stolen as u32)?;
+    let seq = record_op(deps.storage, &env.block, "steal_half", None, None)?;
+    let mut res = Response::new()
+        .add_attribute("action", "steal_half")
+        .add_attribute("removed", keys.len().to_string())
+        .add_attribute("op_seq", seq.to_string());
+    if !funds.is_empty() {
+        res = res.add_message(BankMsg::Send {
+            to_address: recipient.into_string(),
+            amount: funds,
+        });
+    }
+    Ok(res)
+}
+
+pub fn migrate(mut deps: DepsMut, env: Env, msg: MigrateMsg) -> Result<Response, ContractError> {
+    let res = match msg {
+        // the version stored by the queue example, if any, is not ours
+        MigrateMsg::FromQueueExample {} => migrate_from_queue_example(deps.branch(), &env)?,
+        MigrateMsg::WideKeys {} => {
+            check_upgrade(deps.storage)?;
+            if ITEM_BOUNDS.may_load(deps.storage)?.is_some() {
+                return Err(ContractError::InvalidConfig {
+                    msg: "Items already have wide keys".to_string(),
+                });
+            }
+            let bounds = migrate_one_byte_keys(deps.storage)?;
+            if CONFIG.may_load(deps.storage)?.is_none() {
+                seed_default_state(deps.branch(), &env)?;
+            } else {
+                recompute_stats(deps.storage)?;
+            }
+            Response::new()
+                .add_attribute("action", "migrate")
+                .add_attribute("from", "one_byte_keys")
+                .add_attribute("items", bounds.count.to_string())
+        }
+        MigrateMsg::Upgrade {} => upgrade(deps.branch(), &env)?,
+    };
+    set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
+    Ok(res)
+}
+
+/// Returns the stored version, unset for contracts from before versions were
+/// stored. Fails if it belongs to another contract or newer code.
+fn check_upgrade(storage: &dyn Storage) -> Result<Option<ContractVersion>, ContractError> {
+    let stored = match CONTRACT.may_load(storage)? {
+        Some(stored) => stored,
+        None => return Ok(None),
+    };
+    if stored.contract != CONTRACT_NAME {
+        return Err(ContractError::WrongContract {
+            contract: stored.contract,
+        });
+    }
+    if parse_version(&stored.version)? > parse_version(CONTRACT_VERSION)? {
+        return Err(ContractError::Downgrade {
+            stored: stored.version,
+            current: CONTRACT_VERSION.to_string(),
+        });
+    }
+    Ok(Some(stored))
+}
+
+/// Major, minor and patch of a version, ignoring any pre-release or build suffix
+fn parse_version(version: &str) -> StdResult<(u64, u64, u64)> {
+    let invalid = || StdError::generic_err(format!("Invalid version {}", version));
+    let core = version.split(['-', '+']).next().unwrap_or_default();
+    let parts = core
+        .split('.')
+        .map(|part| part.parse::<u64>().map_err(|_| invalid()))
+        .collect::<StdResult<Vec<_>>>()?;
+    match parts[..] {
+        [major, minor, patch] => Ok((major, minor, patch)),
+        _ => Err(invalid()),
+    }
+}
+
+fn upgrade(deps: DepsMut, env: &Env) -> Result<Response, ContractError> {
+    let stored = check_upgrade(deps.storage)?;
+    let mut res = Response::new()
+        .add_attribute("action", "migrate")
+        .add_attribute(
+            "from_version",
+            stored
+                .as_ref()
+                .map_or("none", |stored| stored.version.as_str()),
+        );
+    // Contracts from before versions were stored may still keep their items
+    // under one-byte keys and have no config or stats. Later versions add
+    // their steps below, keyed on the stored version.
+    if stored.is_none() {
+        if ITEM_BOUNDS.may_load(deps.storage)?.is_none() {
+            let bounds = migrate_one_byte_keys(deps.storage)?;
+            res = res.add_attribute("wide_keys", bounds.count.to_string());
+        }
+        if CONFIG.may_load(deps.storage)?.is_none() {
+            seed_default_state(deps, env)?;
+            res = res.add_attribute("config", "default");
+        } else if STATS.may_load(deps.storage)?.is_none() {
+            recompute_stats(deps.storage)?;
+            res = res.add_attribute("stats", "recomputed");
+        }
+    }
+    Ok(res.add_attribute("to_version", CONTRACT_VERSION))
+}
+
+/// Moves items from the one-byte keys at the storage root, where the queue
+/// example and version 1 of the key layout keep them, to `item_storage_key`.
+/// There are at most 256 of them, so they are looked up one by one rather
+/// than found with a scan over all of storage.
+fn migrate_one_byte_keys(storage: &mut dyn Storage) -> StdResult<ItemBounds> {
+    let mut bounds = ItemBounds::default();
+    for byte in 0..=u8::MAX {
+        let data = match storage.get(&[byte]) {
+            Some(data) => data,
+            None => continue,
+        };
+        let index = byte as u64;
+        storage.remove(&[byte]);
+        storage.set(&item_storage_key(index), &data);
+        bounds.tail = index + 1;
+        bounds.count += 1;
+    }
+    ITEM_BOUNDS.save(storage, &bounds)?;
+    Ok(bounds)
+}
+
+/// The queue example keeps nothing but its items, so everything else starts
+/// out like a default instantiation
+fn migrate_from_queue_example(mut deps: DepsMut, env: &Env) -> Result<Response, ContractError> {
+    if CONFIG.may_load(deps.storage)?.is_some() {
+        return Err(ContractError::InvalidConfig {
+            msg: "Contract already has a stack config".to_string(),
+        });
+    }
+    migrate_one_byte_keys(deps.storage)?;
+    let count = seed_default_state(deps.branch(), env)?;
+    Ok(Response::new()
+        .add_attribute("action", "migrate")
+        .add_attribute("from", "queue_example")
+        .add_attribute("items", count.to_string()))
+}
+
+/// Sets up the state of a default instantiation around items that are
+/// already under wide keys, for contracts that kept nothing else: the queue
+/// example and stacks from before the config. Their items are all integers.
+/// Returns the number of items.
+fn seed_default_state(deps: DepsMut, env: &Env) -> Result<u32, ContractError> {
+    let info: ContractInfoResponse = deps.querier.query(
+        &WasmQuery::ContractInfo {
+            contract_addr: env.contract.address.to_string(),
+        }
+        .into(),
+    )?;
+    // a migration always has an admin, the creator is just a fallback
+    let owner = deps
+        .api
+        .addr_validate(&info.admin.unwrap_or(info.creator))?;
+    let config = Config {
+        owner,
+        max_ops_per_block: None,
+        push_restriction: None,
+        hidden_values: false,
+        capacity: None,
+        nois_proxy: None,
+        lottery: None,
+        pop_auction: None,
+        claim_timeout: None,
+        data_encoding: DataEncoding::default(),
+        pop_type_url: None,
+        clear_chunk_size: None,
+        pushers: None,
+        poppers: None,
+        pop_into_targets: None,
+        limits: Limits::default(),
+        cold_admin_key: None,
+    };
+    CONFIG.save(deps.storage, &config)?;
+    PAUSED.save(deps.storage, &false)?;
+
+    let values = items(deps.storage, None, None, Order::Ascending)
+        .map(|(_, data)| from_slice::<Item>(&data).map(|item| item.value))
+        .collect::<StdResult<Vec<_>>>()?;
+    let mut recent = RecentValues::new(DEFAULT_MOVING_AVERAGE_WINDOW);
+    for value in &values {
+        recent.push(*value);
+    }
+    RECENT_VALUES.save(deps.storage, &recent)?;
+    DEPTH.save(deps.storage, &(values.len() as u32))?;
+    // fills in VALUE_COUNTS as well
+    recompute_stats(deps.storage)?;
+    Ok(values.len() as u32)
+}
+
+pub fn execute(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    msg: ExecuteMsg,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    check_limits(&config.limits, &msg)?;
+    check_cold_admin_key(deps.storage, &config, &msg)?;
+    // random pops pay for the randomness and lottery pushes for their ticket,
+    // no other message expects funds
+    let paid = match msg {
+        ExecuteMsg::RequestRandomPop { .. } | ExecuteMsg::BidForPop {} => true,
+        // named stacks take neither, see handle_named_push
+        ExecuteMsg::Push {
+            ref beneficiary,
+            stack: None,
+            ..
+        } => beneficiary.is_some() || CONFIG.load(deps.storage)?.lottery.is_some(),
+        ExecuteMsg::PushRef { .. } => CONFIG.load(deps.storage)?.lottery.is_some(),
+        _ => false,
+    };
+    if !paid {
+        reject_funds(&info)?;
+    }
+    if let ExecuteMsg::Pop { .. }
+    | ExecuteMsg::Dequeue {}
+    | ExecuteMsg::PopInto { .. }
+    | ExecuteMsg::PopMany { .. }
+    | ExecuteMsg::RevealPop { .. }
+    | ExecuteMsg::RequestRandomPop { .. }
+    | ExecuteMsg::BidForPop {}
+    | ExecuteMsg::ClaimItem {}
+    | ExecuteMsg::CompleteItem { .. } = msg
+    {
+        check_pop_allowed(deps.storage, &info.sender)?;
+    }
+    // every message that moves items or funds counts toward the circuit
+    // breaker. Configuration, council and stats messages are exempt, so the
+    // owner can still recover a paused contract.
+    match msg {
+        ExecuteMsg::Push {
+            value,
+            beneficiary,
+            stack: None,
+        } => {
+            let tripped = track_activity(deps.storage, &env.block)?;
+            Ok(handle_push(deps, env, info, value, beneficiary, None)?.add_events(tripped))
+        }
+        ExecuteMsg::Push {
+            value,
+            beneficiary,
+            stack: Some(name),
+        } => {
+            if beneficiary.is_some() {
+                return Err(ContractError::NamedStacksUnavailable {
+                    action: "push with beneficiary".to_string(),
+                });
+            }
+            let tripped = track_activity(deps.storage, &env.block)?;
+            Ok(handle_named_push(deps, env, info, name, value)?.add_events(tripped))
+        }
+        ExecuteMsg::PushRef {
+            value,
+            contract,
+            key,
+            smart,
+        } => {
+            let tripped = track_activity(deps.storage, &env.block)?;
+            let reference = ItemRef {
+                contract: deps.api.addr_validate(&contract)?,
+                key,
+                smart: smart.unwrap_or(false),
+            };
+            let value = Payload::Int(value);
+            Ok(handle_push(deps, env, info, value, None, Some(reference))?.add_events(tripped))
+        }
+        ExecuteMsg::Pop { stack: None } => {
+            let tripped = track_activity(deps.storage, &env.block)?;
+            let res = handle_pop(deps, env, info, Order::Descending, None)?;
+            Ok(res.add_events(tripped))
+        }
+        ExecuteMsg::Dequeue {} => {
+            let tripped = track_activity(deps.storage, &env.block)?;
+            let res = handle_pop(deps, env, info, Order::Ascending, None)?;
+            Ok(res.add_events(tripped))
+        }
+        ExecuteMsg::Pop { stack: Some(name) } => {
+            let tripped = track_activity(deps.storage, &env.block)?;
+            Ok(handle_named_pop(deps, env, name)?.add_events(tripped))
+        }
+        ExecuteMsg::PopInto {
+            contract,
+            msg_template,
+        } => {
+            let tripped = track_activity(deps.storage, &env.block)?;
+            let contract = deps.api.addr_validate(&contract)?;
+            check_pop_target(deps.storage, &env, &contract)?;
+            check_json(msg_template.replace(VALUE_PLACEHOLDER, "null").as_bytes())?;
+            let into = Some((contract, msg_template));
+            let res = handle_pop(deps, env, info, Order::Descending, into)?;
+            Ok(res.add_events(tripped))
+        }
+        ExecuteMsg::PushMany { values } => handle_push_many(deps, env, info, values),
+        ExecuteMsg::PopMany { count } => handle_pop_many(deps, env, info, count),
+        ExecuteMsg::PushHidden { commitment } => {
+            let tripped = track_activity(deps.storage, &env.block)?;
+            Ok(handle_push_hidden(deps, env, info, commitment)?.add_events(tripped))
+        }
+        ExecuteMsg::RevealPop { value, salt } => {
+            let tripped = track_activity(deps.storage, &env.block)?;
+            Ok(handle_reveal_pop(deps, env, value, salt)?.add_events(tripped))
+        }
+        ExecuteMsg::ResetCircuitBreaker {} => handle_reset_circuit_breaker(deps, info),
+        ExecuteMsg::QuarantineItem { index } => {
+            let tripped = track_activity(deps.storage, &env.block)?;
+            Ok(handle_quarantine_item(deps, env, info, index)?.add_events(tripped))
+        }
+        ExecuteMsg::ReleaseItem { index } => {
+            let tripped = track_activity(deps.storage, &env.block)?;
+            Ok(handle_release_item(deps, env, info, index)?.add_events(tripped))
+        }
+        ExecuteMsg::RemoveItem { index } => {
+            let tripped = track_activity(deps.storage, &env.block)?;
+            Ok(handle_remove_item(deps, env, info, index)?.add_events(tripped))
+        }
+        ExecuteMsg::Batch { ops } => handle_batch(deps, env, info, ops),
+        ExecuteMsg::RequestRandomPop { weighted } => {
+            let tripped = track_activity(deps.storage, &env.block)?;
+            let weighted = weighted.unwrap_or(false);
+            Ok(handle_request_random_pop(deps, env, info, weighted)?.add_events(tripped))
+        }
+        ExecuteMsg::BidForPop {} => {
+            let tripped = track_activity(deps.storage, &env.block)?;
+            Ok(handle_bid_for_pop(deps, env, info)?.add_events(tripped))
+        }
+        ExecuteMsg::ClaimItem {} => {
+            let tripped = track_activity(deps.storage, &env.block)?;
+            Ok(handle_claim_item(deps, env, info)?.add_events(tripped))
+        }
+        ExecuteMsg::CompleteItem { index, result } => {
+            let tripped = track_activity(deps.storage, &env.block)?;
+            Ok(handle_complete_item(deps, env, info, index, result)?.add_events(tripped))
+        }
+        ExecuteMsg::Claim { index } => {
+            let tripped = track_activity(deps.storage, &env.block)?;
+            Ok(handle_claim(deps, env, info, index)?.add_events(tripped))
+        }
+        ExecuteMsg::Clear {} => {
+            let tripped = track_activity(deps.storage, &env.block)?;
+            Ok(handle_clear(deps, env, info)?.add_events(tripped))
+        }
+        ExecuteMsg::CreateStack { name } => handle_create_stack(deps, info, name),
+        ExecuteMsg::RemoveStack { name } => handle_remove_stack(deps, info, name),
+        ExecuteMsg::ContinueClear {} => {
+            let tripped = track_activity(deps.storage, &env.block)?;
+            Ok(handle_continue_clear(deps, env)?.add_events(tripped))
+        }
+        ExecuteMsg::Shuffle { seed } => {
+            let tripped = track_activity(deps.storage, &env.block)?;
+            Ok(handle_shuffle(deps, env, info, seed)?.add_events(tripped))
+        }
+        ExecuteMsg::Recompute {} => handle_recompute(deps, info),
+        ExecuteMsg::UpdateOwner { owner } => handle_update_owner(deps, info, owner),
+        ExecuteMsg::UpdatePermissions { pushers, poppers } => {
+            handle_update_permissions(deps, info, pushers, poppers)
+        }
+        ExecuteMsg::UpdatePopIntoTargets { targets } => {
+            handle_update_pop_into_targets(deps, info, targets)
+        }
+        ExecuteMsg::UpdateColdAdminKey { key } => handle_update_cold_admin_key(deps, info, key),
+        ExecuteMsg::AdminOpWithSig {
+            op,
+            pubkey,
+            signature,
+            nonce,
+        } => handle_admin_op_with_sig(deps, env, *op, pubkey, signature, nonce),
+        ExecuteMsg::Propose { msg } => handle_propose(deps, info, *msg),
+        ExecuteMsg::Vote { proposal_id } => handle_vote(deps, info, proposal_id),
+        ExecuteMsg::Execute { proposal_id } => handle_execute_proposal(deps, env, proposal_id),
+        ExecuteMsg::NoisReceive { callback } => handle_nois_receive(deps, env, info, callback),
+    }
+}
+
+/// Weight of the sender in the council, failing if there is no council or
+/// the sender is not a member
+fn council_weight(storage: &dyn Storage, sender: &Addr) -> Result<u64, ContractError> {
+    COUNCIL
+        .may_load(storage)?
+        .and_then(|council| council.weight(sender))
+        .ok_or(ContractError::NotCouncilMember {})
+}
+
+fn handle_propose(
+    deps: DepsMut,
+    info: MessageInfo,
+    msg: ExecuteMsg,
+) -> Result<Response, ContractError> {
+    let weight = council_weight(deps.storage, &info.sender)?;
+    match msg {
+        ExecuteMsg::ResetCircuitBreaker {}
+        | ExecuteMsg::QuarantineItem { .. }
+        | ExecuteMsg::ReleaseItem { .. }
+        | ExecuteMsg::RemoveItem { .. }
+        | ExecuteMsg::Clear {}
+        | ExecuteMsg::Shuffle { .. }
+        | ExecuteMsg::CreateStack { .. }
+        | ExecuteMsg::RemoveStack { .. }
+        | ExecuteMsg::UpdateOwner { .. }
+        | ExecuteMsg::Recompute {}
+        | ExecuteMsg::UpdatePermissions { .. }
+        | ExecuteMsg::UpdatePopIntoTargets { .. }
+        | ExecuteMsg::UpdateColdAdminKey { .. } => {}
+        _ => return Err(ContractError::NotOwnerMessage {}),
+    }
+    let proposal_id = PROPOSAL_COUNT.may_load(deps.storage)?.unwrap_or_default() + 1;
+    PROPOSAL_COUNT.save(deps.storage, &proposal_id)?;
+    let proposal = Proposal {
+        proposer: info.sender.clone(),
+        msg,
+        votes: weight,
+        executed: false,
+    };
+    PROPOSALS.save(deps.storage, U64Key::new(proposal_id), &proposal)?;
+    VOTES.save(
+        deps.storage,
+        (U64Key::new(proposal_id), &info.sender),
+        &Empty {},
+    )?;
+    Ok(Response::new()
+        .add_attribute("action", "propose")
+        .add_attribute("proposal_id", proposal_id.to_string())
+        .set_data(encode_data(deps.storage, &proposal_id)?))
+}
+
+fn handle_vote(
+    deps: DepsMut,
+    info: MessageInfo,
+    proposal_id: u64,
+) -> Result<Response, ContractError> {
+    let weight = council_weight(deps.storage, &info.sender)?;
+    let mut proposal = PROPOSALS
+        .may_load(deps.storage, U64Key::new(proposal_id))?
+        .ok_or(ContractError::UnknownProposal { proposal_id })?;
+    if proposal.executed {
+        return Err(ContractError::ProposalExecuted { proposal_id });
+    }
+    let vote_key = (U64Key::new(proposal_id), &info.sender);
+    if VOTES.has(deps.storage, vote_key.clone()) {
+        return Err(ContractError::AlreadyVoted { proposal_id });
+    }
+    VOTES.save(deps.storage, vote_key, &Empty {})?;
+    proposal.votes += weight;
+    PROPOSALS.save(deps.storage, U64Key::new(proposal_id), &proposal)?;
+    Ok(Response::new()
+        .add_attribute("action", "vote")
+        .add_attribute("proposal_id", proposal_id.to_string())
+        .add_attribute("votes", proposal.votes.to_string()))
+}
+
+fn handle_execute_proposal(
+    mut deps: DepsMut,
+    env: Env,
+    proposal_id: u64,
+) -> Result<Response, ContractError> {
+    let mut proposal = PROPOSALS
+        .may_load(deps.storage, U64Key::new(proposal_id))?
+        .ok_or(ContractError::UnknownProposal { proposal_id })?;
+    if proposal.executed {
+        return Err(ContractError::ProposalExecuted { proposal_id });
+    }
+    // the council can't change after instantiation, so it must still exist
+    if proposal.votes < COUNCIL.load(deps.storage)?.threshold {
+        return Err(ContractError::ThresholdNotReached { proposal_id });
+    }
+    proposal.executed = true;
+    PROPOSALS.save(deps.storage, U64Key::new(proposal_id), &proposal)?;
+    // the contract is the owner while a council is set
+    let info = MessageInfo {
+        sender: env.contract.address.clone(),
+        funds: vec![],
+    };
+    let res = execute(deps.branch(), env, info, proposal.msg)?;
+    Ok(res
+        .add_attribute("action", "execute")
+        .add_attribute("proposal_id", proposal_id.to_string()))
+}
+
+fn handle_batch(
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    ops: Vec<ExecuteMsg>,
+) -> Result<Response, ContractError> {
+    let mut res = Response::new().add_attribute("action", "batch");
+    let mut results = Vec::with_capacity(ops.len());
+    for op in ops {
+        if contains_batch(&op) {
+            return Err(ContractError::NestedBatch {});
+        }
+        let op_res = execute(deps.branch(), env.clone(), info.clone(), op)?;
+        res.messages.extend(op_res.messages);
+        res.attributes.extend(op_res.attributes);
+        res.events.extend(op_res.events);
+        results.push(op_res.data);
+    }
+    Ok(res.set_data(encode_data(deps.storage, &BatchResponse { results })?))
+}
+
+/// Whether `msg` is a batch or wraps one. Proposals can't hold a batch, see
+/// handle_propose.
+fn contains_batch(msg: &ExecuteMsg) -> bool {
+    match msg {
+        ExecuteMsg::Batch { .. } => true,
+        ExecuteMsg::AdminOpWithSig { op, .. } => contains_batch(op),
+        _ => false,
+    }
+}
+
+/// Encodes the data of a response in the configured encoding
+fn encode_data<T: Serialize>(storage: &dyn Storage, data: &T) -> Result<Binary, ContractError> {
+    match CONFIG.load(storage)?.data_encoding {
+        DataEncoding::Json => Ok(to_binary(data)?),
+        DataEncoding::Bincode => bincode::serialize(data)
+            .map(Binary)
+            .map_err(|err| StdError::serialize_err(std::any::type_name::<T>(), err).into()),
+    }
+}
+
+/// Like `encode_data` for a popped item as stored, which is already JSON.
+/// Wrapped in a protobuf `Any` if a pop type URL is configured.
+fn encode_popped_item(storage: &dyn Storage, data: Vec<u8>) -> Result<Binary, ContractError> {
+    let config = CONFIG.load(storage)?;
+    let encoded = match config.data_encoding {
+        DataEncoding::Json => Binary(data),
+        DataEncoding::Bincode => encode_data(storage, &from_slice::<Item>(&data)?)?,
+    };
+    Ok(match config.pop_type_url {
+        Some(type_url) => Binary(encode_any(&type_url, &encoded)),
+        None => encoded,
+    })
+}
+
+const DEFAULT_MAX_BATCH_OPS: u32 = 20;
+const DEFAULT_MAX_MANY_ITEMS: u32 = 50;
+const DEFAULT_MAX_PAYLOAD_BYTES: u32 = 1024;
+const DEFAULT_MAX_TEMPLATE_BYTES: u32 = 4096;
+
+/// Checks the sizes of `msg` against the configured limits. The ops of a
+/// batch and a proposal are checked when they are executed.
+fn check_limits(limits: &Limits, msg: &ExecuteMsg) -> Result<(), ContractError> {
+    let max_items = limits.many_items.unwrap_or(DEFAULT_MAX_MANY_ITEMS);
+    let max_payload = limits.payload_bytes.unwrap_or(DEFAULT_MAX_PAYLOAD_BYTES);
+    match msg {
+        ExecuteMsg::Push { value, .. } => {
+            check_limit("Payload size", payload_size(value), max_payload)
+        }
+        ExecuteMsg::PushRef { key, .. } => check_limit("Key size", key.len(), max_payload),
+        ExecuteMsg::PushMany { values } => {
+            check_limit("Item count", values.len(), max_items)?;
+            values
+                .iter()
+                .try_for_each(|value| check_limit("Payload size", payload_size(value), max_payload))
+        }
+        ExecuteMsg::PopMany { count } => check_limit("Item count", *count as usize, max_items),
+        ExecuteMsg::PopInto { msg_template, .. } => check_limit(
+            "Template size",
+            msg_template.len(),
+            limits.template_bytes.unwrap_or(DEFAULT_MAX_TEMPLATE_BYTES),
+        ),
+        ExecuteMsg::CompleteItem { result, .. } => {
+            check_limit("Result size", result.len(), max_payload)
+        }
+        ExecuteMsg::Batch { ops } => check_limit(
+            "Batch ops",
+            ops.len(),
+            limits.batch_ops.unwrap_or(DEFAULT_MAX_BATCH_OPS),
+        ),
+        _ => Ok(()),
+    }
+}
+
+fn check_limit(what: &str, size: usize, max: u32) -> Result<(), ContractError> {
+    if size > max as usize {
+        return Err(ContractError::LimitExceeded {
+            what: what.to_string(),
+            size,
+            max,
+        });
+    }
+    Ok(())
+}
+
+/// Bytes of a string or bytes payload, 0 for integers
+fn payload_size(payload: &Payload) -> usize {
+    match payload {
+        Payload::Int(_) => 0,
+        Payload::String(value) => value.len(),
+        Payload::Bytes { bytes } => bytes.len(),
+    }
+}
+
+/// Fails if any coins were sent along, so they don't get stuck in the contract
+fn reject_funds(info: &MessageInfo) -> Result<(), ContractError> {
+    nonpayable(info)?;
+    Ok(())
+}
+
+fn ensure_owner(storage: &dyn Storage, sender: &Addr) -> Result<(), ContractError> {
+    if *sender != CONFIG.load(storage)?.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+    Ok(())
+}
+
+fn handle_recompute(deps: DepsMut, info: MessageInfo) -> Result<Response, ContractError> {
+    ensure_owner(deps.storage, &info.sender)?;
+    let tracked = STATS.may_load(deps.storage)?;
+    let tracked_counts = value_counts(deps.storage)?;
+    let stats = recompute_stats(deps.storage)?;
+    let consistent = tracked == Some(stats) && tracked_counts == value_counts(deps.storage)?;
+    Ok(Response::new()
+        .add_attribute("action", "recompute")
+        .add_attribute("consistent", consistent.to_string()))
+}
+
+fn value_counts(storage: &dyn Storage) -> StdResult<Vec<(Vec<u8>, u32)>> {
+    VALUE_COUNTS
+        .range(storage, None, None, Order::Ascending)
+        .collect()
+}
+
+/// Computes the stats and value counts from scratch and stores them
+fn recompute_stats(storage: &mut dyn Storage) -> StdResult<Stats> {
+    let hidden = CONFIG.load(storage)?.hidden_values;
+    let mut stats = Stats::default();
+    let mut counts = BTreeMap::<i32, u32>::new();
+    for (_, data) in active_items(storage, Order::Ascending) {
+        stats.count += 1;
+        if hidden {
+            continue;
+        }
+        if let Some(value) = from_slice::<Item>(&data)?.numeric() {
+            stats.sum += value as i64;
+            stats.min = Some(stats.min.map_or(value, |min| min.min(value)));
+            stats.max = Some(stats.max.map_or(value, |max| max.max(value)));
+            *counts.entry(value).or_default() += 1;
+        }
+    }
+    let stale: Vec<_> = VALUE_COUNTS
+        .keys(storage, None, None, Order::Ascending)
+        .collect();
+    for key in stale {
+        VALUE_COUNTS.remove(storage, &key);
+    }
+    for (value, count) in counts {
+        VALUE_COUNTS.save(storage, &value_key(value), &count)?;
+    }
+    STATS.save(storage, &stats)?;
+    Ok(stats)
+}
+
+fn handle_update_owner(
+    deps: DepsMut,
+    info: MessageInfo,
+    owner: String,
+) -> Result<Response, ContractError> {
+    ensure_owner(deps.storage, &info.sender)?;
+    let owner = deps.api.addr_validate(&owner)?;
+    CONFIG.update(deps.storage, |mut config| -> StdResult<_> {
+        config.owner = owner.clone();
+        Ok(config)
+    })?;
+    Ok(Response::new()
+        .add_attribute("action", "update_owner")
+        .add_attribute("owner", owner))
+}
+
+fn handle_update_permissions(
+    deps: DepsMut,
+    info: MessageInfo,
+    pushers: Option<Vec<String>>,
+    poppers: Option<Vec<String>>,
+) -> Result<Response, ContractError> {
+    ensure_owner(deps.storage, &info.sender)?;
+    let pushers = validate_allowlist(deps.as_ref(), pushers)?;
+    let poppers = validate_allowlist(deps.as_ref(), poppers)?;
+    CONFIG.update(deps.storage, |mut config| -> StdResult<_> {
+        config.pushers = pushers;
+        config.poppers = poppers;
+        Ok(config)
+    })?;
+    Ok(Response::new().add_attribute("action", "update_permissions"))
+}
+
+fn handle_update_pop_into_targets(
+    deps: DepsMut,
+    info: MessageInfo,
+    targets: Option<Vec<String>>,
+) -> Result<Response, ContractError> {
+    ensure_owner(deps.storage, &info.sender)?;
+    let targets = validate_allowlist(deps.as_ref(), targets)?;
+    CONFIG.update(deps.storage, |mut config| -> StdResult<_> {
+        config.pop_into_targets = targets;
+        Ok(config)
+    })?;
+    Ok(Response::new().add_attribute("action", "update_pop_into_targets"))
+}
+
+/// With a cold admin key set, the destructive owner messages only run as the
+/// op of a signed `ExecuteMsg::AdminOpWithSig`
+fn check_cold_admin_key(
+    storage: &dyn Storage,
+    config: &Config,
+    msg: &ExecuteMsg,
+) -> Result<(), ContractError> {
+    let action = match msg {
+        ExecuteMsg::Clear {} => "clear",
+        ExecuteMsg::Shuffle { .. } => "shuffle",
+        ExecuteMsg::RemoveItem { .. } => "remove_item",
+        ExecuteMsg::UpdateOwner { .. } => "update_owner",
+        ExecuteMsg::UpdatePermissions { .. } => "update_permissions",
+        ExecuteMsg::UpdatePopIntoTargets { .. } => "update_pop_into_targets",
+        ExecuteMsg::UpdateColdAdminKey { .. } => "update_cold_admin_key",
+        _ => return Ok(()),
+    };
+    if config.cold_admin_key.is_some() && !SIGNED_OP.may_load(storage)?.unwrap_or(false) {
+        return Err(ContractError::ColdAdminKeyRequired {
+            action: action.to_string(),
+        });
+    }
+    Ok(())
+}
+
+fn validate_pubkey(key: &Binary) -> Result<(), ContractError> {
+    // compressed or uncompressed secp256k1 keys
+    if key.len() != 33 && key.len() != 65 {
+        return Err(ContractError::InvalidConfig {
+            msg: "Cold admin key must be a secp256k1 public key".to_string(),
+        });
+    }
+    Ok(())
+}
+
+fn handle_update_cold_admin_key(
+    deps: DepsMut,
+    info: MessageInfo,
+    key: Option<Binary>,
+) -> Result<Response, ContractError> {
+    ensure_owner(deps.storage, &info.sender)?;
+    if let Some(key) = &key {
+        validate_pubkey(key)?;
+    }
+    CONFIG.update(deps.storage, |mut config| -> StdResult<_> {
+        config.cold_admin_key = key;
+        Ok(config)
+    })?;
+    Ok(Response::new().add_attribute("action", "update_cold_admin_key"))
+}
+
+fn handle_admin_op_with_sig(
+    mut deps: DepsMut,
+    env: Env,
+    op: ExecuteMsg,
+    pubkey: Binary,
+    signature: Binary,
+    nonce: u64,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    let cold_key = config
+        .cold_admin_key
+        .ok_or(ContractError::NoColdAdminKey {})?;
+    if pubkey != cold_key {
+        return Err(ContractError::WrongColdAdminKey {});
+    }
+    let expected = ADMIN_NONCE.may_load(deps.storage)?.unwrap_or_default() + 1;
+    if nonce != expected {
+        return Err(ContractError::WrongNonce { expected });
+    }
+    let doc = AdminOpSignDoc {
+        chain_id: env.block.chain_id.clone(),
+        contract: env.contract.address.to_string(),
+        nonce,
+        op,
+    };
+    let hash = Sha256::digest(&to_vec(&doc)?);
+    // malformed signatures fail verification too
+    let valid = deps
+        .api
+        .secp256k1_verify(&hash, &signature, &pubkey)
+        .unwrap_or(false);
+    if !valid {
+        return Err(ContractError::InvalidSignature {});
+    }
+    ADMIN_NONCE.save(deps.storage, &nonce)?;
+
+    let info = MessageInfo {
+        sender: config.owner,
+        funds: vec![],
+    };
+    SIGNED_OP.save(deps.storage, &true)?;
+    let res = execute(deps.branch(), env, info, doc.op)?;
+    SIGNED_OP.remove(deps.storage);
+    Ok(res
+        .add_attribute("admin_op", "signed")
+        .add_attribute("admin_nonce", nonce.to_string()))
+}
+
+/// PopInto must not call this contract: the call would run with the
+/// contract as sender, which passes every allowlist and is the owner in
+/// council mode
+fn check_pop_target(
+    storage: &dyn Storage,
+    env: &Env,
+    contract: &Addr,
+) -> Result<(), ContractError> {
+    let allowed = *contract != env.contract.address
+        && match CONFIG.load(storage)?.pop_into_targets {
+            Some(targets) => targets.contains(contract),
+            None => true,
+        };
+    if !allowed {
+        return Err(ContractError::InvalidPopTarget {
+            contract: contract.to_string(),
+        });
+    }
+    Ok(())
+}
+
+/// The owner may always push and pop, anyone else only if allowed by the list
+fn check_allowlist(config: &Config, list: &Option<Vec<Addr>>, sender: &Addr) -> bool {
+    match list {
+        Some(list) => *sender == config.owner || list.contains(sender),
+        None => true,
+    }
+}
+
+fn check_pop_allowed(storage: &dyn Storage, sender: &Addr) -> Result<(), ContractError> {
+    let config = CONFIG.load(storage)?;
+    if !check_allowlist(&config, &config.poppers, sender) {
+        return Err(ContractError::Unauthorized {});
+    }
+    Ok(())
+}
+
+/// Counts the operation against the current block and rejects it if the
+/// contract is paused. The operation that exceeds the threshold still goes
+/// through, but pauses the contract; the returned event reports that.
+fn track_activity(
+    storage: &mut dyn Storage,
+    block: &BlockInfo,
+) -> Result<Option<Event>, ContractError> {
+    if PAUSED.load(storage)? {
+        return Err(ContractError::Paused {});
+    }
+
+    let mut activity = BLOCK_ACTIVITY.may_load(storage)?.unwrap_or_default();
+    if activity.height != block.height {
+        activity = BlockActivity {
+            height: block.height,
+            ops: 0,
+        };
+    }
+    activity.ops += 1;
+    BLOCK_ACTIVITY.save(storage, &activity)?;
+
+    let max_ops = CONFIG.load(storage)?.max_ops_per_block;
+    match max_ops {
+        Some(max_ops) if activity.ops > max_ops => {
+            PAUSED.save(storage, &true)?;
+            Ok(Some(
+                Event::new("circuit_breaker_tripped")
+                    .add_attribute("height", block.height.to_string())
+                    .add_attribute("ops", activity.ops.to_string())
+                    .add_attribute("max_ops_per_block", max_ops.to_string()),
+            ))
+        }
+        _ => Ok(None),
+    }
+}
+
+fn handle_reset_circuit_breaker(
+    deps: DepsMut,
+    info: MessageInfo,
+) -> Result<Response, ContractError> {
+    ensure_owner(deps.storage, &info.sender)?;
+    PAUSED.save(deps.storage, &false)?;
+    BLOCK_ACTIVITY.remove(deps.storage);
+    Ok(Response::new().add_attribute("action", "reset_circuit_breaker"))
+}
+
+const DEFAULT_CAPACITY: u32 = 256;
+// aggregate queries scan the whole stack, which gets too expensive beyond this
+const MAX_CAPACITY: u32 = 100_000;
+
+fn capacity(config: &Config) -> u32 {
+    config.capacity.unwrap_or(DEFAULT_CAPACITY)
+}
+
+/// Iterates over the stack items with indexes from `start` (inclusive) to
+/// `end` (exclusive). The keys are the raw storage keys, see `item_index`.
+fn items<'a>(
+    storage: &'a dyn Storage,
+    start: Option<u64>,
+    end: Option<u64>,
+    order: Order,
+) -> impl Iterator<Item = Pair> + 'a {
+    let start = item_storage_key(start.unwrap_or(0));
+    let end = match end {
+        Some(end) => item_storage_key(end),
+        // the first key after the items namespace
+        None => {
+            let mut end = items_prefix();
+            *end.last_mut().unwrap() += 1;
+            end
+        }
+    };
+    storage.range(Some(&start), Some(&end), order)
+}
+
+/// Like `items`, but without the quarantined ones
+fn active_items<'a>(storage: &'a dyn Storage, order: Order) -> impl Iterator<Item = Pair> + 'a {
+    items(storage, None, None, order)
+        .filter(move |(key, _)| !QUARANTINED.has(storage, U64Key::new(item_index(key))))
+}
+
+/// Like `active_items`, but also without the items a worker claimed. Claimed
+/// items still count as part of the stack otherwise.
+fn unclaimed_items<'a>(
+    storage: &'a dyn Storage,
+    order: Order,
+    now: Timestamp,
+) -> impl Iterator<Item = Pair> + 'a {
+    active_items(storage, order).filter(move |(_, data)| {
+        let claim = from_slice::<ItemMeta>(data)
+            .ok()
+            .and_then(|meta| meta.claim);
+        !matches!(claim, Some(claim) if now < claim.expires_at)
+    })
+}
+
+/// Maps an item index to its storage key, failing for indexes not in the stack
+fn item_key(storage: &dyn Storage, index: u64) -> StdResult<Vec<u8>> {
+    let key = item_storage_key(index);
+    if storage.get(&key).is_none() {
+        return Err(StdError::not_found("Item"));
+    }
+    Ok(key)
+}
+
+fn handle_push(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    value: Payload,
+    beneficiary: Option<String>,
+    reference: Option<ItemRef>,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    if config.hidden_values {
+        return Err(ContractError::ValuesHidden {
+            instead: "push_hidden".to_string(),
+        });
+    }
+    check_push_allowed(deps.as_ref(), &config, &info.sender)?;
+    let escrow = match beneficiary {
+        Some(_) if config.lottery.is_some() => {
+            return Err(ContractError::LotteryMode {
+                action: "push with beneficiary".to_string(),
+            })
+        }
+        Some(beneficiary) => {
+            if info.funds.is_empty() {
+                return Err(PaymentError::NoFunds {}.into());
+            }
+            Some(Escrow {
+                beneficiary: deps.api.addr_validate(&beneficiary)?,
+                funds: info.funds.clone(),
+            })
+        }
+        None => None,
+    };
+    if let Some(lottery) = &config.lottery {
+        buy_ticket(deps.storage, &info, lottery)?;
+    }
+    let (value, payload) = split_payload(value);
+    let item = Item {
+        value,
+        sender: Some(info.sender),
+        height: Some(env.block.height),
+        time: Some(env.block.time),
+        escrow,
+        claim: None,
+        reference,
+        payload,
+        exceeded_by: None,
+    };
+    let data = to_vec(&item)?;
+    let index = push(deps.storage, &item)?;
+    index_item(deps.storage, index, &env.block)?;
+    update_depth(deps.storage, env.block.height, 1)?;
+    if let Some(value) = item.numeric() {
+        increment_value_count(deps.storage, value)?;
+        let mut recent = RECENT_VALUES
+            .may_load(deps.storage)?
+            .unwrap_or_else(|| RecentValues::new(DEFAULT_MOVING_AVERAGE_WINDOW));
+        recent.push(value);
+        RECENT_VALUES.save(deps.storage, &recent)?;
+    }
+    record_block_metrics(deps.storage, env.block.height, 1, 0)?;
+    let seq = record_op(
+        deps.storage,
+        &env.block,
+        "push",
+        Some(index),
+        Some(Binary(data)),
+    )?;
+    Ok(Response::new()
+        .add_attribute("action", "push")
+        .add_attribute("op_seq", seq.to_string()))
+}
+
+/// Integers are stored as the item value, anything else as its payload
+fn split_payload(value: Payload) -> (i32, Option<Payload>) {
+    match value {
+        Payload::Int(value) => (value, None),
+        payload => (0, Some(payload)),
+    }
+}
+
+fn handle_push_many(
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    values: Vec<Payload>,
+) -> Result<Response, ContractError> {
+    // every ticket would need its own payment
+    if CONFIG.load(deps.storage)?.lottery.is_some() {
+        return Err(ContractError::LotteryMode {
+            action: "push_many".to_string(),
+        });
+    }
+    let mut res = Response::new()
+        .add_attribute("action", "push_many")
+        .add_attribute("count", values.len().to_string());
+    for value in values {
+        let tripped = track_activity(deps.storage, &env.block)?;
+        let push_res = handle_push(deps.branch(), env.clone(), info.clone(), value, None, None)?;
+        res = res
+            .add_event(Event::new("push").add_attributes(push_res.attributes))
+            .add_events(tripped);
+    }
+    Ok(res)
+}
+
+fn handle_pop_many(
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    count: u32,
+) -> Result<Response, ContractError> {
+    // each pop would have to win its own auction
+    if CONFIG.load(deps.storage)?.pop_auction.is_some() {
+        return Err(ContractError::AuctionMode {
+            action: "pop_many".to_string(),
+        });
+    }
+    // quarantined items are not in the depth, claimed ones are caught below
+    let available = match DEPTH.may_load(deps.storage)? {
+        Some(depth) => depth,
+        None => ITEM_BOUNDS.load(deps.storage)?.count,
+    };
+    if count > available {
+        return Err(ContractError::NotEnoughItems {
+            requested: count,
+            available,
+        });
+    }
+    let mut res = Response::new()
+        .add_attribute("action", "pop_many")
+        .add_attribute("count", count.to_string());
+    let mut items = Vec::with_capacity(count as usize);
+    for popped in 0..count {
+        let tripped = track_activity(deps.storage, &env.block)?;
+        let pop_res = handle_pop(
+            deps.branch(),
+            env.clone(),
+            info.clone(),
+            Order::Descending,
+            None,
+        )?;
+        let data = pop_res.data.ok_or(ContractError::NotEnoughItems {
+            requested: count,
+            available: popped,
+        })?;
+        res.messages.extend(pop_res.messages);
+        res = res
+            .add_event(Event::new("pop").add_attributes(pop_res.attributes))
+            .add_events(tripped);
+        items.push(data);
+    }
+    Ok(res.set_data(encode_data(deps.storage, &PopManyResponse { items })?))
+}
+
+fn handle_push_hidden(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    commitment: Binary,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    if !config.hidden_values {
+        return Err(ContractError::HiddenValuesDisabled {});
+    }
+    check_push_allowed(deps.as_ref(), &config, &info.sender)?;
+    if commitment.len() != 32 {
+        return Err(ContractError::InvalidCommitment {});
+    }
+    let sealed = SealedItem {
+        commitment,
+        sender: Some(info.sender),
+        height: Some(env.block.height),
+        time: Some(env.block.time),
+    };
+    let data = to_vec(&sealed)?;
+    let index = push(deps.storage, &sealed)?;
+    index_item(deps.storage, index, &env.block)?;
+    update_depth(deps.storage, env.block.height, 1)?;
+    record_block_metrics(deps.storage, env.block.height, 1, 0)?;
+    let seq = record_op(
+        deps.storage,
+        &env.block,
+        "push_hidden",
+        Some(index),
+        Some(Binary(data)),
+    )?;
+    Ok(Response::new()
+        .add_attribute("action", "push_hidden")
+        .add_attribute("op_seq", seq.to_string()))
+}
+
+const MAX_STACK_NAME_LEN: usize = 64;
+
+fn handle_create_stack(
+    deps: DepsMut,
+    info: MessageInfo,
+    name: String,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    ensure_owner(deps.storage, &info.sender)?;
+    let unsupported = if config.hidden_values {
+        Some("hidden values")
+    } else if config.lottery.is_some() {
+        Some("lottery mode")
+    } else if config.pop_auction.is_some() {
+        Some("pop auctions")
+    } else {
+        None
+    };
+    if let Some(action) = unsupported {
+        return Err(ContractError::NamedStacksUnavailable {
+            action: action.to_string(),
+        });
+    }
+    if name.is_empty() || name.chars().count() > MAX_STACK_NAME_LEN {
+        return Err(ContractError::InvalidStackName {});
+    }
+    if STACKS.has(deps.storage, &name) {
+        return Err(ContractError::StackExists { name });
+    }
+    STACKS.save(deps.storage, &name, &NamedStack::default())?;
+    Ok(Response::new()
+        .add_attribute("action", "create_stack")
+        .add_attribute("stack", name))
+}
+
+fn handle_remove_stack(
+    deps: DepsMut,
+    info: MessageInfo,
+    name: String,
+) -> Result<Response, ContractError> {
+    ensure_owner(deps.storage, &info.sender)?;
+    let stack = load_stack(deps.storage, &name)?;
+    if stack.count > 0 {
+        return Err(ContractError::StackNotEmpty { name });
+    }
+    STACKS.remove(deps.storage, &name);
+    Ok(Response::new()
+        .add_attribute("action", "remove_stack")
+        .add_attribute("stack", name))
+}
+
+fn load_stack(storage: &dyn Storage, name: &str) -> Result<NamedStack, ContractError> {
+    STACKS
+        .may_load(storage, name)?
+        .ok_or_else(|| ContractError::UnknownStack {
+            name: name.to_string(),
+        })
+}
+
+/// Pushes a plain item to a named stack. Named stacks share the capacity and
+/// the push restriction of the default stack, but none of its indexes.
+fn handle_named_push(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    name: String,
+    value: Payload,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    check_push_allowed(deps.as_ref(), &config, &info.sender)?;
+    let mut stack = load_stack(deps.storage, &name)?;
+    if stack.count >= capacity(&config) {
+        return Err(ContractError::StackFull {});
+    }
+    let (value, payload) = split_payload(value);
+    let item = Item {
+        value,
+        sender: Some(info.sender),
+        height: Some(env.block.height),
+        time: Some(env.block.time),
+        escrow: None,
+        claim: None,
+        reference: None,
+        payload,
+        exceeded_by: None,
+    };
+    let index = stack.tail;
+    NAMED_ITEMS.save(deps.storage, (&name, U64Key::new(index)), &item)?;
+    stack.tail += 1;
+    stack.count += 1;
+    STACKS.save(deps.storage, &name, &stack)?;
+    record_block_metrics(deps.storage, env.block.height, 1, 0)?;
+    let data = Some(Binary(to_vec(&item)?));
+    let seq = record_named_op(deps.storage, &env.block, "push", &name, index, data)?;
+    Ok(Response::new()
+        .add_attribute("action", "push")
+        .add_attribute("stack", name)
+        .add_attribute("op_seq", seq.to_string()))
+}
+
+fn handle_named_pop(deps: DepsMut, env: Env, name: String) -> Result<Response, ContractError> {
+    let mut stack = load_stack(deps.storage, &name)?;
+    let last = NAMED_ITEMS
+        .prefix(&name)
+        .range(deps.storage, None, None, Order::Descending)
+        .next()
+        .transpose()?;
+    let mut res = Response::new();
+    if let Some((key, item)) = last {
+        let index = u64_from_key(&key)?;
+        NAMED_ITEMS.remove(deps.storage, (&name, U64Key::new(index)));
+        stack.count -= 1;
+        STACKS.save(deps.storage, &name, &stack)?;
+        record_block_metrics(deps.storage, env.block.height, 0, 1)?;
+        let seq = record_named_op(deps.storage, &env.block, "pop", &name, index, None)?;
+        res = res
+            .add_attribute("action", "pop")
+            .add_attribute("stack", name)
+            .add_attribute("op_seq", seq.to_string());
+        res.data = Some(encode_popped_item(deps.storage, to_vec(&item)?)?);
+    }
+    Ok(res)
+}
+
+/// Adds the ticket price paid with `info` to the pot
+fn buy_ticket(
+    storage: &mut dyn Storage,
+    info: &MessageInfo,
+    lottery: &LotteryConfig,
+) -> Result<(), ContractError> {
+    let paid = must_pay(info, &lottery.denom)?;
+    if paid != lottery.ticket_price {
+        return Err(ContractError::WrongTicketPrice {
+            price: lottery.ticket_price,
+            denom: lottery.denom.clone(),
+        });
+    }
+    let pot = POT.may_load(storage)?.unwrap_or_default();
+    POT.save(storage, &(pot + paid))?;
+    Ok(())
+}
+
+fn check_push_allowed(deps: Deps, config: &Config, sender: &Addr) -> Result<(), ContractError> {
+    if !check_allowlist(config, &config.pushers, sender) {
+        return Err(ContractError::Unauthorized {});
+    }
+    if let Some(restriction) = config.push_restriction {
+        let is_contract = is_contract(deps, sender);
+        match restriction {
+            CallerRestriction::ContractsOnly if !is_contract => {
+                return Err(ContractError::ContractsOnly {});
+            }
+            CallerRestriction::AccountsOnly if is_contract => {
+                return Err(ContractError::AccountsOnly {});
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Only contracts have contract info, so the query fails for any other address
+fn is_contract(deps: Deps, addr: &Addr) -> bool {
+    deps.querier
+        .query::<ContractInfoResponse>(
+            &WasmQuery::ContractInfo {
+                contract_addr: addr.to_string(),
+            }
+            .into(),
+        )
+        .is_ok()
+}
+
+/// Stores the item on top of the stack and returns its index
+fn push<T: Serialize>(storage: &mut dyn Storage, item: &T) -> Result<u64, ContractError> {
+    if CLEARING.may_load(storage)?.unwrap_or(false) {
+        return Err(ContractError::ClearInProgress {});
+    }
+    let config = CONFIG.load(storage)?;
+    let mut bounds = ITEM_BOUNDS.load(storage)?;
+    if bounds.count >= capacity(&config) {
+        return Err(ContractError::StackFull {});
+    }
+    let index = bounds.tail;
+    storage.set(&item_storage_key(index), &to_vec(item)?);
+    bounds.tail += 1;
+    bounds.count += 1;
+    ITEM_BOUNDS.save(storage, &bounds)?;
+    Ok(index)
+}
+
+/// A zero duration would let every bid expire as soon as it is placed
+fn validate_pop_auction(auction: &PopAuctionConfig) -> Result<(), ContractError> {
+    if auction.denom.is_empty() {
+        return Err(ContractError::InvalidConfig {
+            msg: "Pop auction denom must not be empty".to_string(),
+        });
+    }
+    if auction.bidding_seconds == 0 {
+        return Err(ContractError::InvalidConfig {
+            msg: "Bidding seconds must not be zero".to_string(),
+        });
+    }
+    if auction.pop_seconds == Some(0) {
+        return Err(ContractError::InvalidConfig {
+            msg: "Pop seconds must not be zero".to_string(),
+        });
+    }
+    Ok(())
+}
+
+/// Adjusts the number of active items and snapshots it for `height`
+fn update_depth(storage: &mut dyn Storage, height: u64, delta: i32) -> StdResult<()> {
+    let depth = match DEPTH.may_load(storage)? {
+        Some(depth) => depth,
+        // not tracked yet, so this change is already part of the stack
+        None => (active_items(storage, Order::Ascending).count() as i32 - delta) as u32,
+    };
+    let depth = (depth as i32 + delta) as u32;
+    DEPTH.save(storage, &depth)?;
+    update_stats(storage, |stats| {
+        stats.count = depth;
+    })?;
+    record_depth_snapshot(storage, height, depth)
+}
+
+// the metadata shared by Item and SealedItem
+#[derive(Deserialize)]
+struct ItemMeta {
+    #[serde(default)]
+    height: Option<u64>,
+    #[serde(default)]
+    time: Option<Timestamp>,
+    #[serde(default)]
+    escrow: Option<Escrow>,
+    #[serde(default)]
+    claim: Option<WorkClaim>,
+}
+
+/// Pays out the funds held by a removed item, if any
+fn release_escrow(data: &[u8]) -> StdResult<Option<BankMsg>> {
+    let meta: ItemMeta = from_slice(data)?;
+    Ok(meta.escrow.map(|escrow| BankMsg::Send {
+        to_address: escrow.beneficiary.into_string(),
+        amount: escrow.funds,
+    }))
+}
+
+/// Like `release_escrow`, but pays `recipient` instead of the beneficiary.
+/// The winner of a pop auction gets the funds of the item they pop.
+fn release_escrow_to(data: &[u8], recipient: &Addr) -> StdResult<Option<BankMsg>> {
+    let meta: ItemMeta = from_slice(data)?;
+    Ok(meta.escrow.map(|escrow| BankMsg::Send {
+        to_address: recipient.to_string(),
+        amount: escrow.funds,
+    }))
+}
+
+fn index_item(storage: &mut dyn Storage, index: u64, block: &BlockInfo) -> StdResult<()> {
+    HEIGHT_INDEX.save(
+        storage,
+        (U64Key::new(block.height), U64Key::new(index)),
+        &Empty {},
+    )?;
+    TIME_INDEX.save(
+        storage,
+        (U64Key::new(block.time.nanos()), U64Key::new(index)),
+        &Empty {},
+    )
+}
+
+/// Deletes the item at `key` along with its index entries and returns its data
+fn remove_item(storage: &mut dyn Storage, key: &[u8]) -> StdResult<Vec<u8>> {
+    let data = storage
+        .get(key)
+        .ok_or_else(|| StdError::not_found("Item"))?;
+    let meta: ItemMeta = from_slice(&data)?;
+    let index = item_index(key);
+    if let Some(height) = meta.height {
+        HEIGHT_INDEX.remove(storage, (U64Key::new(height), U64Key::new(index)));
+    }
+    if let Some(time) = meta.time {
+        TIME_INDEX.remove(storage, (U64Key::new(time.nanos()), U64Key::new(index)));
+    }
+    storage.remove(key);
+    let mut bounds = ITEM_BOUNDS.load(storage)?;
+    bounds.count -= 1;
+    ITEM_BOUNDS.save(storage, &bounds)?;
+    Ok(data)
+}
+
+/// Stores an item removed with `remove_item` again, under `key`
+fn restore_item(storage: &mut dyn Storage, key: &[u8], data: &[u8]) -> StdResult<()> {
+    let meta: ItemMeta = from_slice(data)?;
+    let index = item_index(key);
+    if let Some(height) = meta.height {
+        HEIGHT_INDEX.save(
+            storage,
+            (U64Key::new(height), U64Key::new(index)),
+            &Empty {},
+        )?;
+    }
+    if let Some(time) = meta.time {
+        TIME_INDEX.save(
+            storage,
+            (U64Key::new(time.nanos()), U64Key::new(index)),
+            &Empty {},
+        )?;
+    }
+    storage.set(key, data);
+    let mut bounds = ITEM_BOUNDS.load(storage)?;
+    bounds.count += 1;
+    ITEM_BOUNDS.save(storage, &bounds)
+}
+
+/// Placeholder for the popped value in the message template of PopInto
+const VALUE_PLACEHOLDER: &str = "{{value}}";
+
+/// Fails unless `msg` is a single JSON value
+fn check_json(msg: &[u8]) -> Result<(), ContractError> {
+    from_slice::<IgnoredAny>(msg).map_err(|err| ContractError::InvalidTemplate {
+        msg: err.to_string(),
+    })?;
+    Ok(())
+}
+
+// #[allow(clippy::unnecessary_wraps)]
+/// Pops the last item, or the first one for `Order::Ascending`. `into` is the
+/// contract and message template of a PopInto.
+fn handle_pop(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    order: Order,
+    into: Option<(Addr, String)>,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    if config.lottery.is_some() {
+        return Err(ContractError::LotteryMode {
+            action: "pop".to_string(),
+        });
+    }
+    if config.hidden_values {
+        return Err(ContractError::ValuesHidden {
+            instead: "reveal_pop".to_string(),
+        });
+    }
+    // find the first element in the queue and extract value
+    let first = unclaimed_items(deps.storage, order, env.block.time).next();
+
+    let mut res = Response::default();
+    if let Some(auction) = &config.pop_auction {
+        if first.is_none() {
+            // the winner keeps the right until the deadline, in case
+            // something is pushed
+            return Err(ContractError::StackEmpty {});
+        }
+        let payment = settle_pop_auction(deps.storage, &env, &info, auction)?;
+        res = res.add_message(BankMsg::Send {
+            to_address: config.owner.to_string(),
+            amount: payment,
+        });
+    }
+    if let Some((key, value)) = first {
+        let action = if into.is_some() { "pop_into" } else { "pop" };
+        // remove from storage and return old value
+        let seq = pop_item(deps.storage, &key, &env.block, action)?;
+        res = res
+            .add_attribute("action", action)
+            .add_attribute("op_seq", seq.to_string());
+        let escrow = match config.pop_auction {
+            Some(_) => release_escrow_to(&value, &info.sender)?,
+            None => release_escrow(&value)?,
+        };
+        if let Some(msg) = escrow {
+            res = res.add_message(msg);
+        }
+        if let Some((contract, msg_template)) = into {
+            // substituted as JSON, so a string can't close its quotes and
+            // add fields of its own
+            let popped = to_vec(&from_slice::<Item>(&value)?.payload())?;
+            let parts: Vec<&[u8]> = msg_template
+                .split(VALUE_PLACEHOLDER)
+                .map(str::as_bytes)
+                .collect();
+            let msg = parts.join(&popped[..]);
+            check_json(&msg)?;
+            // a plain message, so a failing call reverts the whole pop.
+            // No reentrancy guard is needed: the target only runs after this
+            // execution has returned and its writes are in place, so a call
+            // back into Push or Pop is a new execution that sees the
+            // finished pop, never one in the middle of it.
+            res = res.add_message(WasmMsg::Execute {
+                contract_addr: contract.into_string(),
+                msg: Binary(msg),
+                funds: vec![],
+            });
+        }
+        res.data = Some(encode_popped_item(deps.storage, value)?);
+        Ok(res)
+    } else if into.is_some() {
+        Err(ContractError::StackEmpty {})
+    } else {
+        Ok(res)
+    }
+}
+
+/// Checks that the sender won the bidding and returns the winning bid, which
+/// goes to the owner
+fn settle_pop_auction(
+    storage: &mut dyn Storage,
+    env: &Env,
+    info: &MessageInfo,
+    auction: &PopAuctionConfig,
+) -> Result<Vec<Coin>, ContractError> {
+    let bid = POP_BID
+        .may_load(storage)?
+        .ok_or(ContractError::Unauthorized {})?;
+    if env.block.time < bid.ends_at {
+        return Err(ContractError::AuctionOpen {
+            ends_at: bid.ends_at,
+        });
+    }
+    let deadline = auction.pop_deadline(&bid);
+    if env.block.time >= deadline {
+        return Err(ContractError::BidExpired {
+            expired_at: deadline,
+        });
+    }
+    if info.sender != bid.bidder {
+        return Err(ContractError::Unauthorized {});
+    }
+    POP_BID.remove(storage);
+    Ok(coins(bid.amount.u128(), auction.denom.clone()))
+}
+
+fn handle_bid_for_pop(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    let auction = match config.pop_auction {
+        Some(auction) => auction,
+        None => return Err(PaymentError::NonPayable {}.into()),
+    };
+    let amount = must_pay(&info, &auction.denom)?;
+    let mut res = Response::new()
+        .add_attribute("action", "bid_for_pop")
+        .add_attribute("amount", amount.to_string());
+    let ends_at = match POP_BID.may_load(deps.storage)? {
+        // the winner did not pop in time, so this bid starts a new round
+        Some(bid) if env.block.time >= auction.pop_deadline(&bid) => {
+            res = res
+                .add_attribute("forfeited", bid.amount.to_string())
+                .add_message(BankMsg::Send {
+                    to_address: config.owner.into_string(),
+                    amount: coins(bid.amount.u128(), auction.denom.clone()),
+                });
+            env.block.time.plus_seconds(auction.bidding_seconds)
+        }
+        Some(bid) if env.block.time >= bid.ends_at => {
+            return Err(ContractError::AuctionClosed {});
+        }
+        Some(bid) if amount <= bid.amount => {
+            return Err(ContractError::BidTooLow {
+                highest: bid.amount,
+            });
+        }
+        Some(bid) => {
+            res = res.add_message(BankMsg::Send {
+                to_address: bid.bidder.into_string(),
+                amount: coins(bid.amount.u128(), auction.denom),
+            });
+            bid.ends_at
+        }
+        None => env.block.time.plus_seconds(auction.bidding_seconds),
+    };
+    POP_BID.save(
+        deps.storage,
+        &Bid {
+            bidder: info.sender,
+            amount,
+            ends_at,
+        },
+    )?;
+    Ok(res.add_attribute("ends_at", ends_at.to_string()))
+}
+
+/// Removes the visible item at `key` as a pop and returns its op sequence
+fn pop_item(
+    storage: &mut dyn Storage,
+    key: &[u8],
+    block: &BlockInfo,
+    action: &str,
+) -> StdResult<u64> {
+    let data = remove_item(storage, key)?;
+    if let Some(value) = from_slice::<Item>(&data)?.numeric() {
+        decrement_value_count(storage, value)?;
+    }
+    update_depth(storage, block.height, -1)?;
+    record_block_metrics(storage, block.height, 0, 1)?;
+    record_op(storage, block, action, Some(item_index(key)), None)
+}
+
+fn handle_request_random_pop(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    weighted: bool,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    if config.hidden_values {
+        return Err(ContractError::ValuesHidden {
+            instead: "reveal_pop".to_string(),
+        });
+    }
+    if weighted && config.lottery.is_some() {
+        return Err(ContractError::LotteryMode {
+            action: "weighted random pop".to_string(),
+        });
+    }
+    let proxy = config
+        .nois_proxy
+        .ok_or(ContractError::NoRandomnessProxy {})?;
+    // the funds are forwarded as the proxy fee. Sponsored proxies take none,
+    // anything else has to be a single coin.
+    if !info.funds.is_empty() {
+        one_coin(&info)?;
+    }
+    let seq = record_op(deps.storage, &env.block, "request_random_pop", None, None)?;
+    let job_id = format!("random_pop-{}", seq);
+    RANDOM_JOBS.save(deps.storage, &job_id, &RandomJob { weighted })?;
+    Ok(Response::new()
+        .add_message(WasmMsg::Execute {
+            contract_addr: proxy.into_string(),
+            msg: to_binary(&ProxyExecuteMsg::GetNextRandomness {
+                job_id: job_id.clone(),
+            })?,
+            funds: info.funds,
+        })
+        .add_attribute("action", "request_random_pop")
+        .add_attribute("job_id", job_id)
+        .add_attribute("op_seq", seq.to_string()))
+}
+
+/// Pops a random active item. Finding nothing to pop is not an error, so the
+/// delivery still succeeds and the job is cleared.
+fn handle_nois_receive(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    callback: NoisCallback,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    if Some(&info.sender) != config.nois_proxy.as_ref() {
+        return Err(ContractError::Unauthorized {});
+    }
+    let job = match RANDOM_JOBS.may_load(deps.storage, &callback.job_id)? {
+        Some(job) => job,
+        None => {
+            return Err(ContractError::UnknownJob {
+                job_id: callback.job_id,
+            })
+        }
+    };
+    RANDOM_JOBS.remove(deps.storage, &callback.job_id);
+    let randomness = decode_randomness(&callback.randomness)?;
 
-use crate::msg::{InstantiateMsg};
+    let res = Response::new()
+        .add_attribute("action", "random_pop")
+        .add_attribute("job_id", callback.job_id);
+    let picked = if job.weighted {
+        pick_weighted(deps.storage, &randomness, env.block.time)?
+    } else {
+        pick_uniform(deps.storage, &randomness, env.block.time)
+    };
+    let (key, value) = match picked {
+        Some(picked) => picked,
+        None => return Ok(res),
+    };
+    let seq = pop_item(deps.storage, &key, &env.block, "random_pop")?;
+    let mut res = res
+        .add_attribute("index", item_index(&key).to_string())
+        .add_attribute("op_seq", seq.to_string());
+    if let Some(msg) = release_escrow(&value)? {
+        res = res.add_message(msg);
+    }
+    if let Some(lottery) = config.lottery {
+        // items pushed before the sender was recorded can't win, the pot stays
+        let pot = POT.may_load(deps.storage)?.unwrap_or_default();
+        match from_slice::<Item>(&value)?.sender {
+            Some(winner) if !pot.is_zero() => {
+                POT.save(deps.storage, &Uint128::zero())?;
+                res = res
+                    .add_message(BankMsg::Send {
+                        to_address: winner.to_string(),
+                        amount: coins(pot.u128(), lottery.denom),
+                    })
+                    .add_attribute("winner", winner.to_string())
+                    .add_attribute("prize", pot.to_string());
+            }
+            _ => {}
+        }
+    }
+    Ok(res.set_data(encode_popped_item(deps.storage, value)?))
+}
 
-// we store one entry for each item in the stack
-#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
-pub struct Item {
-    pub value: i32,
+fn pick_uniform(storage: &dyn Storage, randomness: &[u8; 32], now: Timestamp) -> Option<Pair> {
+    let count = unclaimed_items(storage, Order::Ascending, now).count() as u64;
+    if count == 0 {
+        return None;
+    }
+    let n = int_below(randomness, count) as usize;
+    unclaimed_items(storage, Order::Ascending, now).nth(n)
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
-#[serde(rename_all = "snake_case")]
-pub enum ExecuteMsg {
-    // Push will add some value to the end of list
-    Push { value: i32 },
-    // Pop will remove value from end of the list
-    Pop {},
+/// None if no unclaimed active item has a positive value
+fn pick_weighted(
+    storage: &dyn Storage,
+    randomness: &[u8; 32],
+    now: Timestamp,
+) -> StdResult<Option<Pair>> {
+    let weighted = unclaimed_items(storage, Order::Ascending, now)
+        .map(|(key, data)| {
+            let weight = from_slice::<Item>(&data)?.numeric().unwrap_or(0).max(0) as u64;
+            Ok((weight, (key, data)))
+        })
+        .collect::<StdResult<Vec<_>>>()?;
+    let total: u64 = weighted.iter().map(|(weight, _)| weight).sum();
+    if total == 0 {
+        return Ok(None);
+    }
+    let mut target = int_below(randomness, total);
+    for (weight, item) in weighted {
+        if target < weight {
+            return Ok(Some(item));
+        }
+        target -= weight;
+    }
+    Ok(None)
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
-#[serde(rename_all = "snake_case")]
-pub enum QueryMsg {
-    // how many items are in the stack
-    Count {},
-    // total of all values in the stack
-    Sum {},
+fn handle_reveal_pop(
+    deps: DepsMut,
+    env: Env,
+    value: i32,
+    salt: Binary,
+) -> Result<Response, ContractError> {
+    if !CONFIG.load(deps.storage)?.hidden_values {
+        return Err(ContractError::HiddenValuesDisabled {});
+    }
+    let (key, sealed) = match active_items(deps.storage, Order::Descending).next() {
+        Some((key, value)) => (key, from_slice::<SealedItem>(&value)?),
+        None => return Err(ContractError::StackEmpty {}),
+    };
+
+    let mut hasher = Sha256::new();
+    hasher.update(value.to_be_bytes());
+    hasher.update(salt.as_slice());
+    if hasher.finalize().as_slice() != sealed.commitment.as_slice() {
+        return Err(ContractError::CommitmentMismatch {});
+    }
+
+    remove_item(deps.storage, &key)?;
+    update_depth(deps.storage, env.block.height, -1)?;
+    record_block_metrics(deps.storage, env.block.height, 0, 1)?;
+    let index = item_index(&key);
+    let seq = record_op(deps.storage, &env.block, "reveal_pop", Some(index), None)?;
+    Ok(Response::new()
+        .add_attribute("action", "reveal_pop")
+        .add_attribute("op_seq", seq.to_string())
+        .set_data(encode_popped_item(
+            deps.storage,
+            to_vec(&Item {
+                value,
+                sender: sealed.sender,
+                height: sealed.height,
+                time: sealed.time,
+                escrow: None,
+                claim: None,
+                reference: None,
+                payload: None,
+                exceeded_by: None,
+            })?,
+        )?))
+}
 
-    List {},
+fn handle_quarantine_item(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    index: u64,
+) -> Result<Response, ContractError> {
+    ensure_owner(deps.storage, &info.sender)?;
+    let key = item_key(deps.storage, index)?;
+    if QUARANTINED.has(deps.storage, U64Key::new(index)) {
+        return Err(ContractError::AlreadyQuarantined { index });
+    }
+    QUARANTINED.save(deps.storage, U64Key::new(index), &Empty {})?;
+    if let Some(value) = visible_value(deps.storage, &key)? {
+        decrement_value_count(deps.storage, value)?;
+    }
+    update_depth(deps.storage, env.block.height, -1)?;
+    let seq = record_op(
+        deps.storage,
+        &env.block,
+        "quarantine_item",
+        Some(index),
+        None,
+    )?;
+    Ok(Response::new()
+        .add_attribute("action", "quarantine_item")
+        .add_attribute("index", index.to_string())
+        .add_attribute("op_seq", seq.to_string()))
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
-pub struct CountResponse {
-    pub count: u32,
+fn handle_release_item(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    index: u64,
+) -> Result<Response, ContractError> {
+    ensure_owner(deps.storage, &info.sender)?;
+    ensure_quarantined(deps.storage, index)?;
+    QUARANTINED.remove(deps.storage, U64Key::new(index));
+    let key = item_key(deps.storage, index)?;
+    if let Some(value) = visible_value(deps.storage, &key)? {
+        increment_value_count(deps.storage, value)?;
+    }
+    update_depth(deps.storage, env.block.height, 1)?;
+    let seq = record_op(deps.storage, &env.block, "release_item", Some(index), None)?;
+    Ok(Response::new()
+        .add_attribute("action", "release_item")
+        .add_attribute("index", index.to_string())
+        .add_attribute("op_seq", seq.to_string()))
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
-pub struct SumResponse {
-    pub sum: i32,
+fn handle_remove_item(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    index: u64,
+) -> Result<Response, ContractError> {
+    ensure_owner(deps.storage, &info.sender)?;
+    ensure_quarantined(deps.storage, index)?;
+    let key = item_key(deps.storage, index)?;
+    let data = remove_item(deps.storage, &key)?;
+    QUARANTINED.remove(deps.storage, U64Key::new(index));
+    let seq = record_op(deps.storage, &env.block, "remove_item", Some(index), None)?;
+    // the funds belong to the beneficiary, not to the owner removing the item
+    let mut res = Response::new()
+        .add_attribute("action", "remove_item")
+        .add_attribute("index", index.to_string())
+        .add_attribute("op_seq", seq.to_string());
+    if let Some(msg) = release_escrow(&data)? {
+        res = res.add_message(msg);
+    }
+    Ok(res)
 }
 
-#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
-pub struct ListResponse {
-    /// List an empty range, both bounded
-    pub empty: Vec<u32>,
-    /// List all IDs lower than 0x20
-    pub early: Vec<u32>,
-    /// List all IDs starting from 0x20
-    pub late: Vec<u32>,
+fn handle_claim_item(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+) -> Result<Response, ContractError> {
+    let timeout = CONFIG
+        .load(deps.storage)?
+        .claim_timeout
+        .ok_or(ContractError::WorkQueueDisabled {})?;
+    let (key, data) = unclaimed_items(deps.storage, Order::Descending, env.block.time)
+        .next()
+        .ok_or(ContractError::StackEmpty {})?;
+    let mut item: Item = from_slice(&data)?;
+    let expires_at = env.block.time.plus_seconds(timeout);
+    item.claim = Some(WorkClaim {
+        worker: info.sender,
+        expires_at,
+    });
+    let data = to_vec(&item)?;
+    deps.storage.set(&key, &data);
+    let index = item_index(&key);
+    let seq = record_op(
+        deps.storage,
+        &env.block,
+        "claim_item",
+        Some(index),
+        Some(Binary(data)),
+    )?;
+    Ok(Response::new()
+        .add_attribute("action", "claim_item")
+        .add_attribute("index", index.to_string())
+        .add_attribute("expires_at", expires_at.to_string())
+        .add_attribute("op_seq", seq.to_string())
+        .set_data(encode_data(
+            deps.storage,
+            &ItemResponse {
+                index,
+                item,
+                resolved: None,
+            },
+        )?))
 }
 
-// A no-op, just empty data
-pub fn instantiate(
-    _deps: DepsMut,
-    _env: Env,
-    _info: MessageInfo,
-    _msg: InstantiateMsg,
-) -> StdResult<Response> {
-    println!("-- Instantiate --");
-    Ok(Response::default())
+fn handle_complete_item(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    index: u64,
+    result: Binary,
+) -> Result<Response, ContractError> {
+    let key = item_key(deps.storage, index)?;
+    if QUARANTINED.has(deps.storage, U64Key::new(index)) {
+        return Err(ContractError::ItemQuarantined { index });
+    }
+    let data = deps.storage.get(&key).unwrap_or_default();
+    match from_slice::<ItemMeta>(&data)?.claim {
+        Some(claim) if claim.worker != info.sender => return Err(ContractError::Unauthorized {}),
+        Some(claim) if env.block.time >= claim.expires_at => {
+            return Err(ContractError::ClaimExpired { index })
+        }
+        Some(_) => {}
+        None => return Err(ContractError::Unauthorized {}),
+    }
+    let seq = pop_item(deps.storage, &key, &env.block, "complete_item")?;
+    let mut res = Response::new()
+        .add_attribute("action", "complete_item")
+        .add_attribute("index", index.to_string())
+        .add_attribute("op_seq", seq.to_string());
+    if let Some(msg) = release_escrow(&data)? {
+        res = res.add_message(msg);
+    }
+    Ok(res.set_data(result))
 }
 
-pub fn execute(
+/// Quarantined items can't be claimed, they keep their funds until they are
+/// released or removed
+fn handle_claim(
     deps: DepsMut,
-    _env: Env,
-    _info: MessageInfo,
-    msg: ExecuteMsg,
-) -> StdResult<Response> {
+    env: Env,
+    info: MessageInfo,
+    index: u64,
+) -> Result<Response, ContractError> {
+    if CONFIG.load(deps.storage)?.hidden_values {
+        // hidden pushes can't carry funds
+        return Err(ContractError::NoEscrow { index });
+    }
+    let key = item_key(deps.storage, index)?;
+    if QUARANTINED.has(deps.storage, U64Key::new(index)) {
+        return Err(ContractError::ItemQuarantined { index });
+    }
+    let data = deps.storage.get(&key).unwrap_or_default();
+    let mut item: Item = from_slice(&data)?;
+    let escrow = item
+        .escrow
+        .take()
+        .ok_or(ContractError::NoEscrow { index })?;
+    if escrow.beneficiary != info.sender {
+        return Err(ContractError::Unauthorized {});
+    }
+    let data = to_vec(&item)?;
+    deps.storage.set(&key, &data);
+    let seq = record_op(
+        deps.storage,
+        &env.block,
+        "claim",
+        Some(index),
+        Some(Binary(data)),
+    )?;
+    Ok(Response::new()
+        .add_message(BankMsg::Send {
+            to_address: escrow.beneficiary.into_string(),
+            amount: escrow.funds,
+        })
+        .add_attribute("action", "claim")
+        .add_attribute("index", index.to_string())
+        .add_attribute("op_seq", seq.to_string()))
+}
+
+const DEFAULT_CLEAR_CHUNK_SIZE: u32 = 50;
+
+fn handle_clear(deps: DepsMut, env: Env, info: MessageInfo) -> Result<Response, ContractError> {
+    ensure_owner(deps.storage, &info.sender)?;
+    if CLEARING.may_load(deps.storage)?.unwrap_or(false) {
+        return Err(ContractError::ClearInProgress {});
+    }
+    clear_chunk(deps, &env, "clear")
+}
+
+fn handle_continue_clear(deps: DepsMut, env: Env) -> Result<Response, ContractError> {
+    if !CLEARING.may_load(deps.storage)?.unwrap_or(false) {
+        return Err(ContractError::NotClearing {});
+    }
+    clear_chunk(deps, &env, "continue_clear")
+}
+
+/// Deletes up to the configured chunk size of items, top of the stack first,
+/// and keeps the clearing marker set while any are left
+fn clear_chunk(deps: DepsMut, env: &Env, action: &str) -> Result<Response, ContractError> {
+    let chunk_size = CONFIG
+        .load(deps.storage)?
+        .clear_chunk_size
+        .unwrap_or(DEFAULT_CLEAR_CHUNK_SIZE) as usize;
+    let keys: Vec<Vec<u8>> = items(deps.storage, None, None, Order::Descending)
+        .map(|(key, _)| key)
+        .take(chunk_size)
+        .collect();
+    let mut res = Response::new();
+    let mut active = 0;
+    for key in &keys {
+        let index = U64Key::new(item_index(key));
+        if QUARANTINED.has(deps.storage, index.clone()) {
+            QUARANTINED.remove(deps.storage, index);
+        } else {
+            if let Some(value) = visible_value(deps.storage, key)? {
+                decrement_value_count(deps.storage, value)?;
+            }
+            active += 1;
+        }
+        let data = remove_item(deps.storage, key)?;
+        if let Some(msg) = release_escrow(&data)? {
+            res = res.add_message(msg);
+        }
+    }
+    update_depth(deps.storage, env.block.height, -active)?;
+    let left = ITEM_BOUNDS.load(deps.storage)?.count;
+    CLEARING.save(deps.storage, &(left > 0))?;
+    // like a shuffle this touches many items, so mirrors have to reload
+    let seq = record_op(deps.storage, &env.block, action, None, None)?;
+    Ok(res
+        .add_attribute("action", action)
+        .add_attribute("removed", keys.len().to_string())
+        .add_attribute("left", left.to_string())
+        .add_attribute("op_seq", seq.to_string()))
+}
+
+/// Permutes the active items among their own slots, so quarantined items keep
+/// their index
+fn handle_shuffle(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    seed: Binary,
+) -> Result<Response, ContractError> {
+    ensure_owner(deps.storage, &info.sender)?;
+    let keys: Vec<Vec<u8>> = active_items(deps.storage, Order::Ascending)
+        .map(|(key, _)| key)
+        .collect();
+    // workers and beneficiaries refer to their item by index, so it has to
+    // stay where it is
+    let mut items = keys
+        .iter()
+        .map(|key| {
+            let data = remove_item(deps.storage, key)?;
+            let meta: ItemMeta = from_slice(&data)?;
+            if meta.claim.is_some() || meta.escrow.is_some() {
+                return Err(ContractError::ItemInUse {
+                    index: item_index(key),
+                });
+            }
+            Ok(data)
+        })
+        .collect::<Result<Vec<_>, ContractError>>()?;
+    Xoshiro256::from_seed(seed.as_slice()).shuffle(&mut items);
+    for (key, data) in keys.iter().zip(items) {
+        restore_item(deps.storage, key, &data)?;
+    }
+    // moves many items, so mirrors have to reload the stack
+    let seq = record_op(deps.storage, &env.block, "shuffle", None, None)?;
+    Ok(Response::new()
+        .add_attribute("action", "shuffle")
+        .add_attribute("count", keys.len().to_string())
+        .add_attribute("op_seq", seq.to_string()))
+}
+
+/// The value of the item at `key`, or None if values are hidden or the item
+/// holds no integer
+fn visible_value(storage: &dyn Storage, key: &[u8]) -> StdResult<Option<i32>> {
+    if CONFIG.load(storage)?.hidden_values {
+        return Ok(None);
+    }
+    let data = storage
+        .get(key)
+        .ok_or_else(|| StdError::not_found("Item"))?;
+    Ok(from_slice::<Item>(&data)?.numeric())
+}
+
+fn ensure_quarantined(storage: &dyn Storage, index: u64) -> Result<(), ContractError> {
+    if !QUARANTINED.has(storage, U64Key::new(index)) {
+        return Err(ContractError::NotQuarantined { index });
+    }
+    Ok(())
+}
+
+pub fn query(deps: Deps, env: Env, msg: QueryMsg) -> StdResult<QueryResponse> {
     match msg {
-        ExecuteMsg::Push { value } => handle_push(deps, value),
-        ExecuteMsg::Pop {} => handle_pop(deps),
+        QueryMsg::Count { stack: None } => to_binary(&stack_count(deps)?),
+        QueryMsg::Count { stack: Some(name) } => to_binary(&named_stack_count(deps, &name)?),
+        QueryMsg::Sum { stack: None } => to_binary(&stack_sum(deps)?),
+        QueryMsg::Sum { stack: Some(name) } => to_binary(&named_stack_sum(deps, &name)?),
+        QueryMsg::List {
+            start_after,
+            limit,
+            order,
+        } => to_binary(&stack_list(
+            deps,
+            PageRequest {
+                start_after,
+                limit,
+                order,
+            },
+        )?),
+        QueryMsg::ListBuckets { order } => {
+            to_binary(&stack_list_buckets(deps, order.unwrap_or(Order::Ascending)))
+        }
+        QueryMsg::CircuitBreaker {} => to_binary(&query_circuit_breaker(deps, env)?),
+        QueryMsg::QuarantinedItems { page } => {
+            to_binary(&query_quarantined_items(deps, page.unwrap_or_default())?)
+        }
+        QueryMsg::MovingAverage { n } => to_binary(&query_moving_average(deps, n)?),
+        QueryMsg::BucketedSum {
+            bucket_seconds,
+            start,
+            end,
+        } => to_binary(&query_bucketed_sum(deps, bucket_seconds, start, end)?),
+        QueryMsg::Percentile { p } => to_binary(&query_percentile(deps, p)?),
+        QueryMsg::Distribution {} => to_binary(&query_distribution(deps)?),
+        QueryMsg::CountByValue { values } => to_binary(&query_count_by_value(deps, values)?),
+        QueryMsg::ItemsByHeight { from, to, page } => to_binary(&query_indexed_items(
+            deps,
+            HEIGHT_INDEX,
+            |item| item.height,
+            (from, to),
+            page.unwrap_or_default(),
+        )?),
+        QueryMsg::ItemsByTime { from, to, page } => to_binary(&query_indexed_items(
+            deps,
+            TIME_INDEX,
+            |item| item.time.map(|time| time.nanos()),
+            (from.nanos(), to.nanos()),
+            page.unwrap_or_default(),
+        )?),
+        QueryMsg::Oldest {} => to_binary(&query_end_item(deps, Order::Ascending)?),
+        QueryMsg::Newest {} => to_binary(&query_end_item(deps, Order::Descending)?),
+        QueryMsg::DepthHistory { heights } => to_binary(&query_depth_history(deps, heights)?),
+        QueryMsg::BlockMetrics { page } => {
+            to_binary(&query_block_metrics(deps, page.unwrap_or_default())?)
+        }
+        QueryMsg::Iterate {
+            cursor,
+            limit,
+            resolve,
+        } => to_binary(&query_iterate(
+            deps,
+            cursor,
+            limit,
+            resolve.unwrap_or(false),
+        )?),
+        QueryMsg::OpsFeed { cursor, limit } => to_binary(&query_ops_feed(deps, cursor, limit)?),
+        QueryMsg::Status {} => to_binary(&query_status(deps)?),
+        QueryMsg::Lottery {} => to_binary(&query_lottery(deps)?),
+        QueryMsg::PopAuction {} => to_binary(&query_pop_auction(deps)?),
+        QueryMsg::KeyLayout {} => to_binary(&query_key_layout()),
+        QueryMsg::Treasury {} => to_binary(&query_treasury(deps, env)?),
+        QueryMsg::Council {} => to_binary(&COUNCIL.may_load(deps.storage)?),
+        QueryMsg::Proposal { proposal_id } => to_binary(&query_proposal(deps, proposal_id)?),
+        QueryMsg::Multi { queries } => to_binary(&query_multi(deps, env, queries)?),
+        QueryMsg::ListStacks {} => to_binary(&query_list_stacks(deps)?),
+        QueryMsg::Stats {} => to_binary(&query_stats(deps)?),
+        QueryMsg::ListChannels {} => to_binary(&query_list_channels(deps)?),
+        QueryMsg::FailedDeliveries { page } => {
+            to_binary(&query_failed_deliveries(deps, page.unwrap_or_default())?)
+        }
+        QueryMsg::Peek { depth } => to_binary(&query_peek(deps, env, depth.unwrap_or(0))?),
     }
 }
 
-const FIRST_KEY: u8 = 0;
+const MAX_MULTI_QUERIES: usize = 10;
 
-fn handle_push(deps: DepsMut, value: i32) -> StdResult<Response> {
-    println!("Push value {}", value);
-    push(deps.storage, value)?;
-    Ok(Response::default())
+fn query_multi(deps: Deps, env: Env, queries: Vec<QueryMsg>) -> StdResult<MultiResponse> {
+    if queries.len() > MAX_MULTI_QUERIES {
+        return Err(StdError::generic_err(format!(
+            "At most {} queries can be combined",
+            MAX_MULTI_QUERIES
+        )));
+    }
+    let results = queries
+        .into_iter()
+        .map(|msg| match msg {
+            QueryMsg::Multi { .. } => Err(StdError::generic_err("Multi queries can't be nested")),
+            msg => query(deps, env.clone(), msg),
+        })
+        .collect::<StdResult<_>>()?;
+    Ok(MultiResponse { results })
 }
 
-fn push(storage: &mut dyn Storage, value: i32) -> StdResult<()> {
-    // find the last element in the queue and extract key
-    let last_item = storage.range(None, None, Order::Ascending).next();
+const MAX_BUCKETS: u64 = 1000;
 
-    let new_key = match last_item {
-        None => FIRST_KEY,
-        Some((key, _)) => {
-            key[0] + 1 // all keys are one byte
+fn query_bucketed_sum(
+    deps: Deps,
+    bucket_seconds: u64,
+    start: u64,
+    end: u64,
+) -> StdResult<BucketedSumResponse> {
+    ensure_values_visible(deps)?;
+    if bucket_seconds == 0 || end <= start {
+        return Err(StdError::generic_err(
+            "Buckets must be non-empty and end after start",
+        ));
+    }
+    if (end - start - 1) / bucket_seconds >= MAX_BUCKETS {
+        return Err(StdError::generic_err(format!(
+            "At most {} buckets can be requested",
+            MAX_BUCKETS
+        )));
+    }
+
+    // items are visited in push order, so the first one seen opens a bucket
+    let mut buckets: Vec<Bucket> = vec![];
+    for (_, data) in active_items(deps.storage, Order::Ascending) {
+        let item: Item = from_slice(&data)?;
+        let (seconds, value) = match (item.time, item.numeric()) {
+            (Some(time), Some(value)) if (start..end).contains(&time.seconds()) => {
+                (time.seconds(), value)
+            }
+            _ => continue,
+        };
+        let bucket_start = start + (seconds - start) / bucket_seconds * bucket_seconds;
+        match buckets.iter_mut().find(|b| b.start == bucket_start) {
+            Some(bucket) => {
+                bucket.count += 1;
+                bucket.sum += value as i64;
+                bucket.close = value;
+                bucket.high = bucket.high.max(value);
+                bucket.low = bucket.low.min(value);
+            }
+            None => buckets.push(Bucket {
+                start: bucket_start,
+                count: 1,
+                sum: value as i64,
+                open: value,
+                close: value,
+                high: value,
+                low: value,
+            }),
         }
+    }
+    buckets.sort_by_key(|b| b.start);
+    Ok(BucketedSumResponse { buckets })
+}
+
+/// Uses the nearest-rank method without interpolation: the result is the
+/// smallest value such that at least p of all values are less or equal to it,
+/// i.e. the value at rank ceil(p * count) of the sorted values (rank 1 for p = 0).
+/// This always returns one of the stored values and never rounds.
+fn query_percentile(deps: Deps, p: Decimal) -> StdResult<PercentileResponse> {
+    ensure_values_visible(deps)?;
+    if p > Decimal::one() {
+        return Err(StdError::generic_err("Percentile must be between 0 and 1"));
+    }
+    let mut values = active_items(deps.storage, Order::Ascending)
+        .map(|(_, data)| Ok(from_slice::<Item>(&data)?.numeric()))
+        .filter_map(StdResult::transpose)
+        .collect::<StdResult<Vec<i32>>>()?;
+    if values.is_empty() {
+        return Ok(PercentileResponse { value: None });
+    }
+    values.sort_unstable();
+
+    let count = values.len() as u128;
+    let floor = (Uint128::from(count) * p).u128();
+    let rank = if Decimal::from_ratio(floor, count) == p {
+        floor
+    } else {
+        floor + 1
     };
-    let new_value = to_vec(&Item { value })?;
+    let index = rank.max(1) as usize - 1;
+    Ok(PercentileResponse {
+        value: Some(values[index]),
+    })
+}
 
-    storage.set(&[new_key], &new_value);
-    Ok(())
+const MICRO: i128 = 1_000_000;
+
+fn query_distribution(deps: Deps) -> StdResult<DistributionResponse> {
+    ensure_values_visible(deps)?;
+    let values = active_items(deps.storage, Order::Ascending)
+        .map(|(_, data)| Ok(from_slice::<Item>(&data)?.numeric().map(i128::from)))
+        .filter_map(StdResult::transpose)
+        .collect::<StdResult<Vec<i128>>>()?;
+    let count = values.len() as i128;
+    if count == 0 {
+        return Ok(DistributionResponse {
+            count: 0,
+            mean_micro: 0,
+            variance_micro: Uint128::zero(),
+            skewness_micro: 0,
+        });
+    }
+
+    // deviations from the mean, in millionths
+    let mean = values.iter().sum::<i128>() * MICRO / count;
+    let deviations: Vec<i128> = values.iter().map(|v| v * MICRO - mean).collect();
+    let variance = deviations.iter().map(|d| d * d / MICRO).sum::<i128>() / count;
+
+    // standardize before cubing so the moments can't overflow
+    let std_dev = isqrt(variance as u128 * MICRO as u128) as i128;
+    let skewness = if std_dev == 0 {
+        0
+    } else {
+        deviations
+            .iter()
+            .map(|d| {
+                let z = d * MICRO / std_dev;
+                z * z / MICRO * z / MICRO
+            })
+            .sum::<i128>()
+            / count
+    };
+
+    Ok(DistributionResponse {
+        count: count as u32,
+        mean_micro: mean as i64,
+        variance_micro: Uint128::from(variance as u128),
+        skewness_micro: skewness as i64,
+    })
 }
 
-// #[allow(clippy::unnecessary_wraps)]
-fn handle_pop(deps: DepsMut) -> StdResult<Response> {
-    // find the first element in the queue and extract value
-    let first = deps.storage.range(None, None, Order::Descending).next();
+/// Integer square root, rounded down
+fn isqrt(n: u128) -> u128 {
+    if n < 2 {
+        return n;
+    }
+    // Newton's method, starting above the root
+    let mut x = n;
+    let mut y = x / 2 + x % 2;
+    while y < x {
+        x = y;
+        y = (x + n / x) / 2;
+    }
+    x
+}
 
-    let mut res = Response::default();
-    if let Some((key, value)) = first {
-        // remove from storage and return old value
-        deps.storage.remove(&key);
-        res.data = Some(Binary(value));
-        Ok(res)
+const MAX_COUNT_BY_VALUE: usize = 100;
+
+fn query_count_by_value(deps: Deps, values: Vec<i32>) -> StdResult<CountByValueResponse> {
+    ensure_values_visible(deps)?;
+    if values.len() > MAX_COUNT_BY_VALUE {
+        return Err(StdError::generic_err(format!(
+            "At most {} values can be counted at once",
+            MAX_COUNT_BY_VALUE
+        )));
+    }
+    let counts = values
+        .into_iter()
+        .map(|value| {
+            let count = VALUE_COUNTS
+                .may_load(deps.storage, &value_key(value))?
+                .unwrap_or_default();
+            Ok(ValueCount { value, count })
+        })
+        .collect::<StdResult<_>>()?;
+    Ok(CountByValueResponse { counts })
+}
+
+const DEFAULT_LIMIT: u32 = 10;
+const MAX_LIMIT: u32 = 30;
+
+/// A page of the items whose `index` entry lies in `range` (both ends
+/// inclusive). `position` gives the first part of the entry of an item, the
+/// one it is sorted by, so the page can start right after the cursor item.
+fn query_indexed_items(
+    deps: Deps,
+    index: Map<(U64Key, U64Key), Empty>,
+    position: fn(&Item) -> Option<u64>,
+    range: (u64, u64),
+    page: PageRequest,
+) -> StdResult<ItemsResponse> {
+    ensure_values_visible(deps)?;
+    let limit = page.limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let order = page.order.unwrap_or(Order::Ascending);
+    let lower = Bound::Inclusive((U64Key::new(range.0), U64Key::new(0)).joined_key());
+    let upper = Bound::Inclusive((U64Key::new(range.1), U64Key::new(u64::MAX)).joined_key());
+    let (min, max) = match page.start_after {
+        None => (lower.clone(), upper.clone()),
+        Some(after) => {
+            // the entry of the cursor item can only be found through the item
+            let item = load_item(deps.storage, after).map_err(|_| {
+                StdError::generic_err(format!(
+                    "Item {} is gone, continue with a range after it",
+                    after
+                ))
+            })?;
+            let position = position(&item.item).ok_or_else(|| StdError::not_found("Entry"))?;
+            let cursor = Bound::Exclusive((U64Key::new(position), U64Key::new(after)).joined_key());
+            match order {
+                Order::Ascending => (cursor, upper.clone()),
+                Order::Descending => (lower.clone(), cursor),
+            }
+        }
+    };
+    let active = |key: &[u8]| -> StdResult<Option<u64>> {
+        // the last 8 bytes of the joined key are the item index
+        let index = u64_from_key(&key[key.len() - 8..])?;
+        Ok(Some(index).filter(|index| !QUARANTINED.has(deps.storage, U64Key::new(*index))))
+    };
+    let entries = index
+        .keys(deps.storage, Some(min), Some(max), order)
+        .filter_map(|key| active(&key).transpose())
+        .map(|index| {
+            let index = index?;
+            Ok((index, load_item(deps.storage, index)?))
+        });
+    let mut total = 0;
+    for key in index.keys(deps.storage, Some(lower), Some(upper), Order::Ascending) {
+        if active(&key)?.is_some() {
+            total += 1;
+        }
+    }
+    let (items, page) = paginate(entries, limit, total)?;
+    Ok(ItemsResponse { items, page })
+}
+
+/// First active item in push order, or the last one for `Order::Descending`
+fn query_end_item(deps: Deps, order: Order) -> StdResult<Option<ItemResponse>> {
+    ensure_values_visible(deps)?;
+    active_items(deps.storage, order)
+        .next()
+        .map(|(key, data)| {
+            Ok(ItemResponse {
+                index: item_index(&key),
+                item: from_slice(&data)?,
+                resolved: None,
+            })
+        })
+        .transpose()
+}
+
+// Cursors are a version byte followed by the last item returned, so the key
+// layout can change without breaking old cursors. Version 1 holds its one-byte
+// storage key, version 2 its index as 8 big-endian bytes.
+const CURSOR_V1: u8 = 1;
+const CURSOR_V2: u8 = 2;
+
+fn encode_cursor(index: u64) -> Binary {
+    let mut cursor = vec![CURSOR_V2];
+    cursor.extend_from_slice(&index.to_be_bytes());
+    Binary(cursor)
+}
+
+/// Returns the index of the item the cursor points at
+fn decode_cursor(cursor: &Binary) -> StdResult<u64> {
+    match cursor.as_slice().split_first() {
+        Some((&CURSOR_V1, &[key])) => Ok(key as u64),
+        Some((&CURSOR_V2, index)) => u64_from_key(index),
+        _ => Err(StdError::generic_err("Invalid cursor")),
+    }
+}
+
+fn query_iterate(
+    deps: Deps,
+    cursor: Option<Binary>,
+    limit: Option<u32>,
+    resolve: bool,
+) -> StdResult<IterateResponse> {
+    ensure_values_visible(deps)?;
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = cursor
+        .as_ref()
+        .map(decode_cursor)
+        .transpose()?
+        .map(|index| index + 1);
+    let mut entries: Vec<Pair> = items(deps.storage, start, None, Order::Ascending)
+        .filter(|(key, _)| !QUARANTINED.has(deps.storage, U64Key::new(item_index(key))))
+        .take(limit + 1)
+        .collect();
+    let cursor = if entries.len() > limit {
+        entries.truncate(limit);
+        entries
+            .last()
+            .map(|(key, _)| encode_cursor(item_index(key)))
     } else {
-        Ok(res)
+        None
+    };
+    let items = entries
+        .into_iter()
+        .map(|(key, data)| {
+            let item: Item = from_slice(&data)?;
+            let resolved = match &item.reference {
+                Some(reference) if resolve => Some(resolve_ref(deps, reference)?),
+                _ => None,
+            };
+            Ok(ItemResponse {
+                index: item_index(&key),
+                item,
+                resolved,
+            })
+        })
+        .collect::<StdResult<_>>()?;
+    Ok(IterateResponse { items, cursor })
+}
+
+/// Fetches the data a reference points at from the other contract
+fn resolve_ref(deps: Deps, reference: &ItemRef) -> StdResult<Binary> {
+    let contract_addr = reference.contract.to_string();
+    if !reference.smart {
+        let data = deps
+            .querier
+            .query_wasm_raw(contract_addr, reference.key.clone())?;
+        return Ok(Binary(data.unwrap_or_default()));
+    }
+    // queried raw, so the response is returned as is instead of parsed
+    let request: QueryRequest<Empty> = WasmQuery::Smart {
+        contract_addr,
+        msg: reference.key.clone(),
+    }
+    .into();
+    match deps.querier.raw_query(&to_vec(&request)?) {
+        SystemResult::Ok(ContractResult::Ok(data)) => Ok(data),
+        SystemResult::Ok(ContractResult::Err(err)) => Err(StdError::generic_err(format!(
+            "Resolving reference failed: {}",
+            err
+        ))),
+        SystemResult::Err(err) => Err(StdError::generic_err(format!(
+            "Resolving reference failed: {}",
+            err
+        ))),
     }
 }
 
-pub fn query(deps: Deps, _env: Env, msg: QueryMsg) -> StdResult<QueryResponse> {
-    match msg {
-        QueryMsg::Count {} => to_binary(&stack_count(deps)),
-        QueryMsg::Sum {} => to_binary(&stack_sum(deps)?),
-        QueryMsg::List {} => to_binary(&stack_list(deps)),
+fn load_item(storage: &dyn Storage, index: u64) -> StdResult<ItemResponse> {
+    let key = item_key(storage, index)?;
+    let data = storage.get(&key).unwrap_or_default();
+    Ok(ItemResponse {
+        index,
+        item: from_slice(&data)?,
+        resolved: None,
+    })
+}
+
+const MAX_DEPTH_HEIGHTS: usize = 100;
+
+/// Heights before the first snapshot report a depth of 0. Heights before the
+/// oldest kept snapshot are an error once old snapshots have been dropped.
+fn query_depth_history(deps: Deps, heights: Vec<u64>) -> StdResult<DepthHistoryResponse> {
+    if heights.len() > MAX_DEPTH_HEIGHTS {
+        return Err(StdError::generic_err(format!(
+            "At most {} heights can be requested at once",
+            MAX_DEPTH_HEIGHTS
+        )));
+    }
+    let pruned = DEPTH_HISTORY_LEN
+        .may_load(deps.storage)?
+        .unwrap_or_default()
+        >= MAX_DEPTH_SNAPSHOTS;
+    let depths = heights
+        .into_iter()
+        .map(|height| {
+            let depth = DEPTH_HISTORY
+                .range(
+                    deps.storage,
+                    None,
+                    Some(Bound::inclusive_int(height)),
+                    Order::Descending,
+                )
+                .next()
+                .transpose()?
+                .map(|(_, depth)| depth);
+            let depth = match depth {
+                Some(depth) => depth,
+                None if pruned => {
+                    return Err(StdError::generic_err(format!(
+                        "The depth at height {} is no longer kept",
+                        height
+                    )))
+                }
+                None => 0,
+            };
+            Ok(DepthAt { height, depth })
+        })
+        .collect::<StdResult<_>>()?;
+    Ok(DepthHistoryResponse { depths })
+}
+
+fn query_block_metrics(deps: Deps, page: PageRequest) -> StdResult<BlockMetricsResponse> {
+    let (min, max, order, limit) = page_bounds(&page);
+    let entries = BLOCK_METRICS
+        .range(deps.storage, min, max, order)
+        .map(|entry| {
+            let (key, BlockMetrics { pushes, pops }) = entry?;
+            let height = u64_from_key(&key)?;
+            Ok((
+                height,
+                BlockMetricsEntry {
+                    height,
+                    pushes,
+                    pops,
+                },
+            ))
+        });
+    let total = BLOCK_METRICS_LEN
+        .may_load(deps.storage)?
+        .unwrap_or_default() as u64;
+    let (blocks, page) = paginate(entries, limit, total)?;
+    Ok(BlockMetricsResponse { blocks, page })
+}
+
+/// Range bounds, order and limit for a page of a map with u64 keys
+fn page_bounds(page: &PageRequest) -> (Option<Bound>, Option<Bound>, Order, usize) {
+    let limit = page.limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let order = page.order.unwrap_or(Order::Ascending);
+    let start = page.start_after.map(Bound::exclusive_int);
+    match order {
+        Order::Ascending => (start, None, order, limit),
+        Order::Descending => (None, start, order, limit),
+    }
+}
+
+/// Collects up to `limit` entries, peeking one further to tell if there is a
+/// next page
+fn paginate<T>(
+    entries: impl Iterator<Item = StdResult<(u64, T)>>,
+    limit: usize,
+    total: u64,
+) -> StdResult<(Vec<T>, PageResponse)> {
+    let mut entries = entries.take(limit + 1).collect::<StdResult<Vec<_>>>()?;
+    let next_key = if entries.len() > limit {
+        entries.truncate(limit);
+        entries.last().map(|(key, _)| *key)
+    } else {
+        None
+    };
+    let page = PageResponse { next_key, total };
+    Ok((entries.into_iter().map(|(_, entry)| entry).collect(), page))
+}
+
+fn query_moving_average(deps: Deps, n: u32) -> StdResult<MovingAverageResponse> {
+    ensure_values_visible(deps)?;
+    let recent = match RECENT_VALUES.may_load(deps.storage)? {
+        Some(recent) => recent,
+        None => RecentValues::new(DEFAULT_MOVING_AVERAGE_WINDOW),
+    };
+    if n == 0 || n > recent.capacity {
+        return Err(StdError::generic_err(format!(
+            "n must be between 1 and the window size {}",
+            recent.capacity
+        )));
+    }
+
+    let (count, sum) = recent
+        .latest(n as usize)
+        .fold((0u32, 0i64), |(count, sum), v| (count + 1, sum + v as i64));
+    let average = if count == 0 {
+        0
+    } else {
+        (sum / count as i64) as i32
+    };
+    Ok(MovingAverageResponse {
+        count,
+        sum,
+        average,
+    })
+}
+
+fn query_quarantined_items(deps: Deps, page: PageRequest) -> StdResult<QuarantinedItemsResponse> {
+    let (min, max, order, limit) = page_bounds(&page);
+    let entries = QUARANTINED.keys(deps.storage, min, max, order).map(|key| {
+        let index = u64_from_key(&key)?;
+        let key = item_key(deps.storage, index)?;
+        let data = Binary(deps.storage.get(&key).unwrap_or_default());
+        Ok((index, QuarantinedItem { index, data }))
+    });
+    let total = QUARANTINED
+        .keys(deps.storage, None, None, Order::Ascending)
+        .count() as u64;
+    let (items, page) = paginate(entries, limit, total)?;
+    Ok(QuarantinedItemsResponse { items, page })
+}
+
+fn query_circuit_breaker(deps: Deps, env: Env) -> StdResult<CircuitBreakerResponse> {
+    let config = CONFIG.load(deps.storage)?;
+    let activity = BLOCK_ACTIVITY.may_load(deps.storage)?.unwrap_or_default();
+    let ops_this_block = if activity.height == env.block.height {
+        activity.ops
+    } else {
+        0
+    };
+    Ok(CircuitBreakerResponse {
+        paused: PAUSED.load(deps.storage)?,
+        max_ops_per_block: config.max_ops_per_block,
+        ops_this_block,
+    })
+}
+
+/// Scans the items only if the contract predates the stats
+fn stack_count(deps: Deps) -> StdResult<CountResponse> {
+    let count = match STATS.may_load(deps.storage)? {
+        Some(stats) => stats.count,
+        None => active_items(deps.storage, Order::Ascending).count() as u32,
+    };
+    Ok(CountResponse { count })
+}
+
+fn query_list_channels(deps: Deps) -> StdResult<ListChannelsResponse> {
+    let channels = CHANNELS
+        .range(deps.storage, None, None, Order::Ascending)
+        .map(|res| res.map(|(_, channel)| channel))
+        .collect::<StdResult<_>>()?;
+    Ok(ListChannelsResponse { channels })
+}
+
+fn query_failed_deliveries(deps: Deps, page: PageRequest) -> StdResult<FailedDeliveriesResponse> {
+    let (min, max, order, limit) = page_bounds(&page);
+    let entries = FAILED_DELIVERIES
+        .range(deps.storage, min, max, order)
+        .map(|entry| entry.map(|(_, delivery)| (delivery.id, delivery)));
+    let total = FAILED_DELIVERIES_LEN
+        .may_load(deps.storage)?
+        .unwrap_or_default() as u64;
+    let (deliveries, page) = paginate(entries, limit, total)?;
+    Ok(FailedDeliveriesResponse { deliveries, page })
+}
+
+fn query_stats(deps: Deps) -> StdResult<Stats> {
+    ensure_values_visible(deps)?;
+    STATS
+        .may_load(deps.storage)?
+        .ok_or_else(|| StdError::generic_err("Stats are not tracked yet, see Recompute"))
+}
+
+fn named_stack(deps: Deps, name: &str) -> StdResult<NamedStack> {
+    STACKS
+        .may_load(deps.storage, name)?
+        .ok_or_else(|| StdError::not_found(format!("stack {}", name)))
+}
+
+fn named_stack_count(deps: Deps, name: &str) -> StdResult<CountResponse> {
+    let count = named_stack(deps, name)?.count;
+    Ok(CountResponse { count })
+}
+
+fn named_stack_sum(deps: Deps, name: &str) -> StdResult<SumResponse> {
+    named_stack(deps, name)?;
+    let values: StdResult<Vec<(Vec<u8>, Item)>> = NAMED_ITEMS
+        .prefix(name)
+        .range(deps.storage, None, None, Order::Ascending)
+        .collect();
+    let sum: i64 = values?
+        .iter()
+        .filter_map(|(_, v)| v.numeric())
+        .map(i64::from)
+        .sum();
+    let sum = i32::try_from(sum).map_err(|_| StdError::generic_err("Sum does not fit into i32"))?;
+    Ok(SumResponse { sum })
+}
+
+fn query_peek(deps: Deps, env: Env, depth: u32) -> StdResult<PeekResponse> {
+    ensure_values_visible(deps)?;
+    let payload = unclaimed_items(deps.storage, Order::Descending, env.block.time)
+        .nth(depth as usize)
+        .map(|(_, data)| from_slice::<Item>(&data).map(|item| item.payload()))
+        .transpose()?;
+    Ok(PeekResponse { payload })
+}
+
+fn query_list_stacks(deps: Deps) -> StdResult<ListStacksResponse> {
+    let stacks = STACKS
+        .range(deps.storage, None, None, Order::Ascending)
+        .map(|res| {
+            let (key, stack) = res?;
+            let name = String::from_utf8(key)
+                .map_err(|_| StdError::generic_err("Corrupted stack name"))?;
+            Ok(StackInfo {
+                name,
+                count: stack.count,
+            })
+        })
+        .collect::<StdResult<_>>()?;
+    Ok(ListStacksResponse { stacks })
+}
+
+fn query_lottery(deps: Deps) -> StdResult<Option<LotteryResponse>> {
+    let lottery = match CONFIG.load(deps.storage)?.lottery {
+        Some(lottery) => lottery,
+        None => return Ok(None),
+    };
+    Ok(Some(LotteryResponse {
+        denom: lottery.denom,
+        ticket_price: lottery.ticket_price,
+        pot: POT.may_load(deps.storage)?.unwrap_or_default(),
+    }))
+}
+
+fn query_key_layout() -> KeyLayoutResponse {
+    KeyLayoutResponse {
+        version: KEY_LAYOUT_VERSION,
+        config: Binary::from(CONFIG_KEY.as_bytes()),
+        depth: Binary::from(DEPTH_KEY.as_bytes()),
+        op_seq: Binary::from(OP_SEQ_KEY.as_bytes()),
+        item_prefix: Binary(items_prefix()),
+    }
+}
+
+fn query_proposal(deps: Deps, proposal_id: u64) -> StdResult<ProposalResponse> {
+    let proposal = PROPOSALS.load(deps.storage, U64Key::new(proposal_id))?;
+    let passed = !proposal.executed && proposal.votes >= COUNCIL.load(deps.storage)?.threshold;
+    let voters = VOTES
+        .prefix(U64Key::new(proposal_id))
+        .keys(deps.storage, None, None, Order::Ascending)
+        .map(|key| String::from_utf8(key).map(Addr::unchecked))
+        .collect::<Result<_, _>>()
+        .map_err(|_| StdError::generic_err("Corrupted voter key"))?;
+    Ok(ProposalResponse {
+        proposal,
+        passed,
+        voters,
+    })
+}
+
+fn query_treasury(deps: Deps, env: Env) -> StdResult<TreasuryResponse> {
+    let balances = deps.querier.query_all_balances(env.contract.address)?;
+    let config = CONFIG.load(deps.storage)?;
+    let mut push_fees = vec![];
+    if let Some(lottery) = config.lottery {
+        let pot = POT.may_load(deps.storage)?.unwrap_or_default();
+        add_coin(&mut push_fees, &lottery.denom, pot);
+    }
+    let mut escrow = vec![];
+    for (_, data) in items(deps.storage, None, None, Order::Ascending) {
+        if let Some(held) = from_slice::<ItemMeta>(&data)?.escrow {
+            for coin in held.funds {
+                add_coin(&mut escrow, &coin.denom, coin.amount);
+            }
+        }
+    }
+    let mut bids = vec![];
+    if let (Some(auction), Some(bid)) = (config.pop_auction, POP_BID.may_load(deps.storage)?) {
+        add_coin(&mut bids, &auction.denom, bid.amount);
+    }
+    let surplus = balances
+        .iter()
+        .map(|balance| {
+            let owed: Uint128 = push_fees
+                .iter()
+                .chain(&escrow)
+                .chain(&bids)
+                .filter(|coin| coin.denom == balance.denom)
+                .map(|coin| coin.amount)
+                .sum();
+            Coin {
+                denom: balance.denom.clone(),
+                amount: balance.amount.saturating_sub(owed),
+            }
+        })
+        .filter(|coin| !coin.amount.is_zero())
+        .collect();
+    Ok(TreasuryResponse {
+        balances,
+        push_fees,
+        escrow,
+        bids,
+        surplus,
+    })
+}
+
+/// Adds `amount` to the coin of `denom` in `coins`, skipping zero amounts
+fn add_coin(coins: &mut Vec<Coin>, denom: &str, amount: Uint128) {
+    if amount.is_zero() {
+        return;
     }
+    match coins.iter_mut().find(|coin| coin.denom == denom) {
+        Some(coin) => coin.amount += amount,
+        None => coins.push(Coin {
+            denom: denom.to_string(),
+            amount,
+        }),
+    }
+}
+
+fn query_pop_auction(deps: Deps) -> StdResult<Option<PopAuctionResponse>> {
+    let auction = match CONFIG.load(deps.storage)?.pop_auction {
+        Some(auction) => auction,
+        None => return Ok(None),
+    };
+    let highest_bid = POP_BID.may_load(deps.storage)?;
+    Ok(Some(PopAuctionResponse {
+        pop_seconds: auction.pop_seconds.unwrap_or(auction.bidding_seconds),
+        pop_deadline: highest_bid.as_ref().map(|bid| auction.pop_deadline(bid)),
+        denom: auction.denom,
+        bidding_seconds: auction.bidding_seconds,
+        highest_bid,
+    }))
+}
+
+fn query_ops_feed(
+    deps: Deps,
+    cursor: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<OpsFeedResponse> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let ops: Vec<OpRecord> = OP_LOG
+        .range(
+            deps.storage,
+            cursor.map(Bound::exclusive_int),
+            None,
+            Order::Ascending,
+        )
+        .take(limit)
+        .map(|entry| entry.map(|(_, op)| op))
+        .collect::<StdResult<_>>()?;
+    let cursor = ops.last().map(|op| op.seq).or(cursor);
+    Ok(OpsFeedResponse { ops, cursor })
+}
+
+fn query_status(deps: Deps) -> StdResult<StatusResponse> {
+    let capacity = capacity(&CONFIG.load(deps.storage)?);
+    let stored = ITEM_BOUNDS.load(deps.storage)?.count;
+    let count = stack_count(deps)?.count;
+    Ok(StatusResponse {
+        is_empty: count == 0,
+        count,
+        capacity,
+        remaining: capacity.saturating_sub(stored),
+        clearing: CLEARING.may_load(deps.storage)?.unwrap_or(false),
+    })
 }
 
-fn stack_count(deps: Deps) -> CountResponse {
-    let count = deps.storage.range(None, None, Order::Ascending).count() as u32;
-    CountResponse { count }
+/// Values of a hidden values stack are not known until they are popped
+fn ensure_values_visible(deps: Deps) -> StdResult<()> {
+    if CONFIG.load(deps.storage)?.hidden_values {
+        return Err(StdError::generic_err("Values are hidden"));
+    }
+    Ok(())
 }
 
+/// Scans the items only if the contract predates the stats
 fn stack_sum(deps: Deps) -> StdResult<SumResponse> {
-    let values: StdResult<Vec<Item>> = deps
-        .storage
-        .range(None, None, Order::Ascending)
+    ensure_values_visible(deps)?;
+    if let Some(stats) = STATS.may_load(deps.storage)? {
+        let sum = i32::try_from(stats.sum)
+            .map_err(|_| StdError::generic_err("Sum does not fit into i32, see Stats"))?;
+        return Ok(SumResponse { sum });
+    }
+    let values: StdResult<Vec<Item>> = active_items(deps.storage, Order::Ascending)
         .map(|(_, v)| from_slice(&v))
         .collect();
-    let sum = values?.iter().fold(0, |s, v| s + v.value);
+    let sum: i64 = values?
+        .iter()
+        .filter_map(Item::numeric)
+        .map(i64::from)
+        .sum();
+    let sum = i32::try_from(sum)
+        .map_err(|_| StdError::generic_err("Sum does not fit into i32, see Stats"))?;
     Ok(SumResponse { sum })
 }
 
+fn stack_list(deps: Deps, page: PageRequest) -> StdResult<ListResponse> {
+    ensure_values_visible(deps)?;
+    let limit = page.limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let order = page.order.unwrap_or(Order::Ascending);
+    let (start, end) = match (order, page.start_after) {
+        (_, None) => (None, None),
+        (Order::Ascending, Some(after)) => (Some(after.saturating_add(1)), None),
+        (Order::Descending, Some(after)) => (None, Some(after)),
+    };
+    let entries = items(deps.storage, start, end, order)
+        .filter(|(key, _)| !QUARANTINED.has(deps.storage, U64Key::new(item_index(key))))
+        .map(|(key, data)| {
+            let index = item_index(&key);
+            let item = ItemResponse {
+                index,
+                item: from_slice(&data)?,
+                resolved: None,
+            };
+            Ok((index, item))
+        });
+    let total = stack_count(deps)?.count as u64;
+    let (items, page) = paginate(entries, limit, total)?;
+    Ok(ListResponse { items, page })
+}
+
 /// Does a range query with both bounds set. Not really useful but to debug an issue
 /// between VM and Wasm: https://github.com/CosmWasm/cosmwasm/issues/508
-fn stack_list(deps: Deps) -> ListResponse {
-    let empty: Vec<u32> = deps
-        .storage
-        .range(Some(b"large"), Some(b"larger"), Order::Ascending)
-        .map(|(k, _)| k[0] as u32)
+fn stack_list_buckets(deps: Deps, order: Order) -> ListBucketsResponse {
+    let empty: Vec<u32> = items(deps.storage, Some(0x20), Some(0x20), order)
+        .map(|(k, _)| item_index(&k) as u32)
         .collect();
-    let early: Vec<u32> = deps
-        .storage
-        .range(None, Some(b"\x20"), Order::Ascending)
-        .map(|(k, _)| k[0] as u32)
+    let early: Vec<u32> = items(deps.storage, None, Some(0x20), order)
+        .map(|(k, _)| item_index(&k) as u32)
         .collect();
-    let late: Vec<u32> = deps
-        .storage
-        .range(Some(b"\x20"), None, Order::Ascending)
-        .map(|(k, _)| k[0] as u32)
+    let late: Vec<u32> = items(deps.storage, Some(0x20), None, order)
+        .map(|(k, _)| item_index(&k) as u32)
         .collect();
-    ListResponse { empty, early, late }
+    ListBucketsResponse { empty, early, late }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cosmwasm_std::testing::{
+        mock_dependencies, mock_env, mock_info, MockApi, MockQuerier, MockStorage,
+        MOCK_CONTRACT_ADDR,
+    };
+    use cosmwasm_std::{coin, OwnedDeps};
+
+    fn instantiate_msg() -> InstantiateMsg {
+        InstantiateMsg {
+            max_ops_per_block: None,
+            push_restriction: None,
+            hidden_values: None,
+            moving_average_window: None,
+            capacity: None,
+            nois_proxy: None,
+            lottery: None,
+            pop_auction: None,
+            claim_timeout: None,
+            council: None,
+            data_encoding: None,
+            pop_type_url: None,
+            clear_chunk_size: None,
+            pushers: None,
+            poppers: None,
+            pop_into_targets: None,
+            limits: None,
+            cold_admin_key: None,
+        }
+    }
+
+    fn setup(msg: InstantiateMsg) -> OwnedDeps<MockStorage, MockApi, MockQuerier> {
+        let mut deps = mock_dependencies(&[]);
+        instantiate(deps.as_mut(), mock_env(), mock_info("creator", &[]), msg).unwrap();
+        let push = ExecuteMsg::Push {
+            value: Payload::Int(5),
+            beneficiary: None,
+            stack: None,
+        };
+        execute(deps.as_mut(), mock_env(), mock_info("anyone", &[]), push).unwrap();
+        deps
+    }
+
+    fn pop_into(contract: &str) -> ExecuteMsg {
+        ExecuteMsg::PopInto {
+            contract: contract.to_string(),
+            msg_template: r#"{"update_owner":{"owner":"attacker"}}"#.to_string(),
+        }
+    }
+
+    #[test]
+    fn pop_into_rejects_own_address() {
+        let mut deps = setup(instantiate_msg());
+        let info = mock_info("attacker", &[]);
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            info,
+            pop_into(MOCK_CONTRACT_ADDR),
+        );
+        assert_eq!(
+            err.unwrap_err(),
+            ContractError::InvalidPopTarget {
+                contract: MOCK_CONTRACT_ADDR.to_string()
+            }
+        );
+        // the item is still there and the owner unchanged
+        assert_eq!(stack_count(deps.as_ref()).unwrap().count, 1);
+        let config = CONFIG.load(&deps.storage).unwrap();
+        assert_eq!(config.owner, Addr::unchecked("creator"));
+    }
+
+    #[test]
+    fn pop_into_only_calls_listed_targets() {
+        let mut deps = setup(InstantiateMsg {
+            pop_into_targets: Some(vec!["target".to_string()]),
+            ..instantiate_msg()
+        });
+        let info = mock_info("anyone", &[]);
+        let err = execute(deps.as_mut(), mock_env(), info.clone(), pop_into("other"));
+        assert_eq!(
+            err.unwrap_err(),
+            ContractError::InvalidPopTarget {
+                contract: "other".to_string()
+            }
+        );
+        let res = execute(deps.as_mut(), mock_env(), info, pop_into("target")).unwrap();
+        assert_eq!(res.messages.len(), 1);
+        assert_eq!(stack_count(deps.as_ref()).unwrap().count, 0);
+    }
+
+    #[test]
+    fn pop_auction_config_is_validated() {
+        let auction = PopAuctionConfig {
+            denom: "ucosm".to_string(),
+            bidding_seconds: 60,
+            pop_seconds: None,
+        };
+        let invalid = vec![
+            PopAuctionConfig {
+                denom: String::new(),
+                ..auction.clone()
+            },
+            PopAuctionConfig {
+                bidding_seconds: 0,
+                ..auction.clone()
+            },
+            PopAuctionConfig {
+                pop_seconds: Some(0),
+                ..auction.clone()
+            },
+        ];
+        for auction in invalid {
+            let mut deps = mock_dependencies(&[]);
+            let msg = InstantiateMsg {
+                pop_auction: Some(auction),
+                ..instantiate_msg()
+            };
+            let err = instantiate(deps.as_mut(), mock_env(), mock_info("creator", &[]), msg);
+            assert!(matches!(
+                err.unwrap_err(),
+                ContractError::InvalidConfig { .. }
+            ));
+        }
+        setup(InstantiateMsg {
+            pop_auction: Some(auction),
+            ..instantiate_msg()
+        });
+    }
+
+    #[test]
+    fn tripped_breaker_rejects_item_and_fund_ops() {
+        let mut deps = setup(InstantiateMsg {
+            max_ops_per_block: Some(1),
+            ..instantiate_msg()
+        });
+        // the second op of the block goes through, but trips the breaker
+        let push = ExecuteMsg::Push {
+            value: Payload::Int(6),
+            beneficiary: None,
+            stack: None,
+        };
+        execute(deps.as_mut(), mock_env(), mock_info("anyone", &[]), push).unwrap();
+        assert!(PAUSED.load(&deps.storage).unwrap());
+
+        let msgs = vec![
+            ExecuteMsg::BidForPop {},
+            ExecuteMsg::ClaimItem {},
+            ExecuteMsg::CompleteItem {
+                index: 0,
+                result: Binary::default(),
+            },
+            ExecuteMsg::Claim { index: 0 },
+            ExecuteMsg::Clear {},
+            ExecuteMsg::ContinueClear {},
+            ExecuteMsg::Shuffle {
+                seed: Binary::from(b"seed".to_vec()),
+            },
+            ExecuteMsg::QuarantineItem { index: 0 },
+            ExecuteMsg::ReleaseItem { index: 0 },
+            ExecuteMsg::RemoveItem { index: 0 },
+        ];
+        for msg in msgs {
+            let info = mock_info("creator", &[]);
+            let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+            assert_eq!(err, ContractError::Paused {});
+        }
+    }
+
+    #[test]
+    fn shuffle_refuses_to_move_claimed_items() {
+        let mut deps = setup(InstantiateMsg {
+            claim_timeout: Some(60),
+            ..instantiate_msg()
+        });
+        let push = ExecuteMsg::Push {
+            value: Payload::Int(6),
+            beneficiary: None,
+            stack: None,
+        };
+        execute(deps.as_mut(), mock_env(), mock_info("anyone", &[]), push).unwrap();
+        let info = mock_info("worker", &[]);
+        execute(deps.as_mut(), mock_env(), info, ExecuteMsg::ClaimItem {}).unwrap();
+        let claimed = active_items(&deps.storage, Order::Ascending)
+            .find(|(_, data)| from_slice::<Item>(data).unwrap().claim.is_some())
+            .map(|(key, _)| item_index(&key))
+            .unwrap();
+
+        let shuffle = ExecuteMsg::Shuffle {
+            seed: Binary::from(b"seed".to_vec()),
+        };
+        let info = mock_info("creator", &[]);
+        let err = execute(deps.as_mut(), mock_env(), info, shuffle).unwrap_err();
+        assert_eq!(err, ContractError::ItemInUse { index: claimed });
+        let data = deps.storage.get(&item_storage_key(claimed)).unwrap();
+        let item: Item = from_slice(&data).unwrap();
+        assert_eq!(item.claim.unwrap().worker, Addr::unchecked("worker"));
+    }
+
+    #[test]
+    fn batch_refuses_batches_wrapped_in_signed_ops() {
+        let mut deps = setup(instantiate_msg());
+        let signed = ExecuteMsg::AdminOpWithSig {
+            op: Box::new(ExecuteMsg::Batch { ops: vec![] }),
+            pubkey: Binary::default(),
+            signature: Binary::default(),
+            nonce: 1,
+        };
+        let batch = ExecuteMsg::Batch { ops: vec![signed] };
+        let info = mock_info("creator", &[]);
+        let err = execute(deps.as_mut(), mock_env(), info, batch).unwrap_err();
+        assert_eq!(err, ContractError::NestedBatch {});
+    }
+
+    #[test]
+    fn random_pop_takes_a_single_fee_coin() {
+        let mut deps = setup(InstantiateMsg {
+            nois_proxy: Some("proxy".to_string()),
+            ..instantiate_msg()
+        });
+        let request = ExecuteMsg::RequestRandomPop { weighted: None };
+        let funds = [coin(1, "unois"), coin(1, "ucosm")];
+        let info = mock_info("anyone", &funds);
+        let err = execute(deps.as_mut(), mock_env(), info, request.clone()).unwrap_err();
+        assert_eq!(err, ContractError::Payment(PaymentError::MultipleDenoms {}));
+
+        let info = mock_info("anyone", &[coin(1, "unois")]);
+        let res = execute(deps.as_mut(), mock_env(), info, request).unwrap();
+        assert_eq!(res.messages.len(), 1);
+    }
+
+    #[test]
+    fn depth_history_keeps_the_latest_blocks() {
+        let mut deps = setup(instantiate_msg());
+        let first = mock_env().block.height;
+        let max = u64::from(MAX_DEPTH_SNAPSHOTS);
+        for height in first + 1..first + max + 5 {
+            update_depth(&mut deps.storage, height, 0).unwrap();
+        }
+        let kept = DEPTH_HISTORY
+            .keys(&deps.storage, None, None, Order::Ascending)
+            .count();
+        assert_eq!(kept as u32, MAX_DEPTH_SNAPSHOTS);
+        let len = DEPTH_HISTORY_LEN.load(&deps.storage).unwrap();
+        assert_eq!(len, MAX_DEPTH_SNAPSHOTS);
+
+        // the oldest kept block still answers, dropped ones are an error
+        let oldest = first + 5;
+        let res = query_depth_history(deps.as_ref(), vec![oldest]).unwrap();
+        assert_eq!(res.depths[0].depth, 1);
+        query_depth_history(deps.as_ref(), vec![oldest - 1]).unwrap_err();
+    }
 }
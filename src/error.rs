@@ -0,0 +1,247 @@
+use cosmwasm_std::{StdError, Timestamp, Uint128};
+use cw_utils::PaymentError;
+use thiserror::Error;
+
+/// Errors returned by instantiate and execute. Each message starts with the
+/// code of its variant in brackets, see `ContractError::code`.
+#[derive(Error, Debug, PartialEq)]
+pub enum ContractError {
+    #[error("[1] {0}")]
+    Std(#[from] StdError),
+
+    #[error("[2] {0}")]
+    Payment(#[from] PaymentError),
+
+    #[error("[3] Unauthorized")]
+    Unauthorized {},
+
+    #[error("[4] Contract is paused by the circuit breaker")]
+    Paused {},
+
+    #[error("[5] Invalid config: {msg}")]
+    InvalidConfig { msg: String },
+
+    #[error("[6] Stack is full")]
+    StackFull {},
+
+    #[error("[7] Stack is empty")]
+    StackEmpty {},
+
+    #[error("[8] Values are hidden, use {instead} instead")]
+    ValuesHidden { instead: String },
+
+    #[error("[9] Hidden values mode is disabled")]
+    HiddenValuesDisabled {},
+
+    #[error("[10] Commitment must be a sha256 hash")]
+    InvalidCommitment {},
+
+    #[error("[11] Value and salt do not match the commitment")]
+    CommitmentMismatch {},
+
+    #[error("[12] Only contracts may push")]
+    ContractsOnly {},
+
+    #[error("[13] Only accounts may push")]
+    AccountsOnly {},
+
+    #[error("[14] Item {index} is already quarantined")]
+    AlreadyQuarantined { index: u64 },
+
+    #[error("[15] Item {index} is not quarantined")]
+    NotQuarantined { index: u64 },
+
+    #[error("[16] Batches can't be nested")]
+    NestedBatch {},
+
+    #[error("[17] No randomness proxy configured")]
+    NoRandomnessProxy {},
+
+    #[error("[18] Unknown randomness job {job_id}")]
+    UnknownJob { job_id: String },
+
+    #[error("[19] A ticket costs {price}{denom}")]
+    WrongTicketPrice { price: Uint128, denom: String },
+
+    #[error("[20] Not available in lottery mode: {action}")]
+    LotteryMode { action: String },
+
+    #[error("[21] Item {index} holds no funds")]
+    NoEscrow { index: u64 },
+
+    #[error("[22] Item {index} is quarantined")]
+    ItemQuarantined { index: u64 },
+
+    #[error("[23] Bidding for the next pop is open until {ends_at}")]
+    AuctionOpen { ends_at: Timestamp },
+
+    #[error("[24] Bidding is over, the highest bidder has to pop first")]
+    AuctionClosed {},
+
+    #[error("[25] Bid must be higher than {highest}")]
+    BidTooLow { highest: Uint128 },
+
+    #[error("[26] Work queue mode is disabled")]
+    WorkQueueDisabled {},
+
+    #[error("[27] The claim on item {index} has expired")]
+    ClaimExpired { index: u64 },
+
+    #[error("[28] Sender is not a council member")]
+    NotCouncilMember {},
+
+    #[error("[29] Unknown proposal {proposal_id}")]
+    UnknownProposal { proposal_id: u64 },
+
+    #[error("[30] Already voted on proposal {proposal_id}")]
+    AlreadyVoted { proposal_id: u64 },
+
+    #[error("[31] Proposal {proposal_id} has not reached the threshold")]
+    ThresholdNotReached { proposal_id: u64 },
+
+    #[error("[32] Proposal {proposal_id} was already executed")]
+    ProposalExecuted { proposal_id: u64 },
+
+    #[error("[33] Only owner messages can be proposed")]
+    NotOwnerMessage {},
+
+    #[error("[34] The stack is being cleared, see ContinueClear")]
+    ClearInProgress {},
+
+    #[error("[35] No clear in progress")]
+    NotClearing {},
+
+    #[error("[36] No stack named {name}")]
+    UnknownStack { name: String },
+
+    #[error("[37] A stack named {name} already exists")]
+    StackExists { name: String },
+
+    #[error("[38] Stack {name} still has items")]
+    StackNotEmpty { name: String },
+
+    #[error("[39] Named stacks don't support {action}")]
+    NamedStacksUnavailable { action: String },
+
+    #[error("[40] Stack names must be 1 to 64 characters long")]
+    InvalidStackName {},
+
+    #[error("[41] Can't pop {requested} items, only {available} are there")]
+    NotEnoughItems { requested: u32, available: u32 },
+
+    #[error("[42] Can't migrate from contract {contract}")]
+    WrongContract { contract: String },
+
+    #[error("[43] Can't downgrade from version {stored} to {current}")]
+    Downgrade { stored: String, current: String },
+
+    #[error("[44] Invalid IBC channel: {msg}")]
+    InvalidChannel { msg: String },
+
+    #[error("[45] Not available in pop auction mode: {action}")]
+    AuctionMode { action: String },
+
+    #[error("[46] Invalid message template: {msg}")]
+    InvalidTemplate { msg: String },
+
+    #[error("[47] PopInto may not call {contract}")]
+    InvalidPopTarget { contract: String },
+
+    #[error("[48] The winning bid expired at {expired_at}, bidding is open again")]
+    BidExpired { expired_at: Timestamp },
+
+    #[error("[49] {what} is {size}, the limit is {max}")]
+    LimitExceeded { what: String, size: usize, max: u32 },
+
+    #[error("[50] No cold admin key is set")]
+    NoColdAdminKey {},
+
+    #[error("[51] Public key is not the cold admin key")]
+    WrongColdAdminKey {},
+
+    #[error("[52] Invalid signature of the admin op")]
+    InvalidSignature {},
+
+    #[error("[53] The nonce of the next admin op is {expected}")]
+    WrongNonce { expected: u64 },
+
+    #[error("[54] {action} has to be signed by the cold admin key, see admin_op_with_sig")]
+    ColdAdminKeyRequired { action: String },
+
+    #[error("[55] Item {index} is claimed or holds funds, so it can't be moved")]
+    ItemInUse { index: u64 },
+}
+
+impl ContractError {
+    /// Stable numeric code of the variant. Codes are never reused, so clients
+    /// can map them to their own messages.
+    pub fn code(&self) -> u32 {
+        match self {
+            ContractError::Std(_) => 1,
+            ContractError::Payment(_) => 2,
+            ContractError::Unauthorized {} => 3,
+            ContractError::Paused {} => 4,
+            ContractError::InvalidConfig { .. } => 5,
+            ContractError::StackFull {} => 6,
+            ContractError::StackEmpty {} => 7,
+            ContractError::ValuesHidden { .. } => 8,
+            ContractError::HiddenValuesDisabled {} => 9,
+            ContractError::InvalidCommitment {} => 10,
+            ContractError::CommitmentMismatch {} => 11,
+            ContractError::ContractsOnly {} => 12,
+            ContractError::AccountsOnly {} => 13,
+            ContractError::AlreadyQuarantined { .. } => 14,
+            ContractError::NotQuarantined { .. } => 15,
+            ContractError::NestedBatch {} => 16,
+            ContractError::NoRandomnessProxy {} => 17,
+            ContractError::UnknownJob { .. } => 18,
+            ContractError::WrongTicketPrice { .. } => 19,
+            ContractError::LotteryMode { .. } => 20,
+            ContractError::NoEscrow { .. } => 21,
+            ContractError::ItemQuarantined { .. } => 22,
+            ContractError::AuctionOpen { .. } => 23,
+            ContractError::AuctionClosed {} => 24,
+            ContractError::BidTooLow { .. } => 25,
+            ContractError::WorkQueueDisabled {} => 26,
+            ContractError::ClaimExpired { .. } => 27,
+            ContractError::NotCouncilMember {} => 28,
+            ContractError::UnknownProposal { .. } => 29,
+            ContractError::AlreadyVoted { .. } => 30,
+            ContractError::ThresholdNotReached { .. } => 31,
+            ContractError::ProposalExecuted { .. } => 32,
+            ContractError::NotOwnerMessage {} => 33,
+            ContractError::ClearInProgress {} => 34,
+            ContractError::NotClearing {} => 35,
+            ContractError::UnknownStack { .. } => 36,
+            ContractError::StackExists { .. } => 37,
+            ContractError::StackNotEmpty { .. } => 38,
+            ContractError::NamedStacksUnavailable { .. } => 39,
+            ContractError::InvalidStackName {} => 40,
+            ContractError::NotEnoughItems { .. } => 41,
+            ContractError::WrongContract { .. } => 42,
+            ContractError::Downgrade { .. } => 43,
+            ContractError::InvalidChannel { .. } => 44,
+            ContractError::AuctionMode { .. } => 45,
+            ContractError::InvalidTemplate { .. } => 46,
+            ContractError::InvalidPopTarget { .. } => 47,
+            ContractError::BidExpired { .. } => 48,
+            ContractError::LimitExceeded { .. } => 49,
+            ContractError::NoColdAdminKey {} => 50,
+            ContractError::WrongColdAdminKey {} => 51,
+            ContractError::InvalidSignature {} => 52,
+            ContractError::WrongNonce { .. } => 53,
+            ContractError::ColdAdminKeyRequired { .. } => 54,
+            ContractError::ItemInUse { .. } => 55,
+        }
+    }
+
+    /// Reads the code back from an error that only arrived as its message,
+    /// like the result of a submessage. The chain adds text around the
+    /// message, so the first code in brackets is taken.
+    pub fn code_of_message(msg: &str) -> Option<u32> {
+        msg.match_indices('[').find_map(|(start, _)| {
+            let rest = &msg[start + 1..];
+            rest.split(']').next()?.parse().ok()
+        })
+    }
+}
@@ -0,0 +1,67 @@
+use schemars::JsonSchema;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use cosmwasm_std::{to_binary, Addr, Coin, CosmosMsg, QuerierWrapper, StdResult, WasmMsg};
+
+use crate::msg::{CountResponse, ExecuteMsg, Payload, QueryMsg, StatusResponse, SumResponse};
+
+/// A deployed stack contract, for calling it from other contracts
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct StackContract(pub Addr);
+
+impl StackContract {
+    pub fn addr(&self) -> Addr {
+        self.0.clone()
+    }
+
+    /// Executes `msg` on the stack without sending funds
+    pub fn call(&self, msg: ExecuteMsg) -> StdResult<CosmosMsg> {
+        self.call_with_funds(msg, vec![])
+    }
+
+    /// Executes `msg` on the stack, e.g. a push holding funds for a beneficiary
+    pub fn call_with_funds(&self, msg: ExecuteMsg, funds: Vec<Coin>) -> StdResult<CosmosMsg> {
+        Ok(WasmMsg::Execute {
+            contract_addr: self.0.to_string(),
+            msg: to_binary(&msg)?,
+            funds,
+        }
+        .into())
+    }
+
+    pub fn push(&self, value: Payload) -> StdResult<CosmosMsg> {
+        self.call(ExecuteMsg::Push {
+            value,
+            beneficiary: None,
+            stack: None,
+        })
+    }
+
+    pub fn pop(&self) -> StdResult<CosmosMsg> {
+        self.call(ExecuteMsg::Pop { stack: None })
+    }
+
+    /// Runs any query and parses the response as `T`
+    pub fn query<T: DeserializeOwned>(
+        &self,
+        querier: &QuerierWrapper,
+        msg: QueryMsg,
+    ) -> StdResult<T> {
+        querier.query_wasm_smart(self.addr(), &msg)
+    }
+
+    pub fn count(&self, querier: &QuerierWrapper) -> StdResult<u32> {
+        let res: CountResponse = self.query(querier, QueryMsg::Count { stack: None })?;
+        Ok(res.count)
+    }
+
+    pub fn sum(&self, querier: &QuerierWrapper) -> StdResult<i32> {
+        let res: SumResponse = self.query(querier, QueryMsg::Sum { stack: None })?;
+        Ok(res.sum)
+    }
+
+    pub fn status(&self, querier: &QuerierWrapper) -> StdResult<StatusResponse> {
+        self.query(querier, QueryMsg::Status {})
+    }
+}
@@ -0,0 +1,264 @@
+use cosmwasm_std::{
+    from_slice, to_binary, Binary, ContractResult, Deps, DepsMut, Env,
+    IbcAcknowledgementWithPacket, IbcBasicResponse, IbcChannel, IbcOrder, IbcPacket,
+    IbcReceiveResponse, Reply, Response, StdError, StdResult, SubMsg, WasmMsg,
+};
+
+use crate::error::ContractError;
+use crate::msg::{ChannelInfo, ExecuteMsg, PacketMsg, StackAck};
+use crate::proto::decode_bytes_field;
+use crate::state::{record_failed_delivery, PendingPacket, CHANNELS, CONFIG, PENDING_PACKET};
+
+pub const IBC_VERSION: &str = "stack-1";
+
+/// Reply id of the packet executions sent by `ibc_packet_receive`
+const PACKET_REPLY_ID: u64 = 1;
+
+/// Only ordered channels are accepted, so pushes and pops of a counterparty
+/// apply in the order they were sent
+pub fn ibc_channel_open(
+    _deps: DepsMut,
+    _env: Env,
+    channel: IbcChannel,
+) -> Result<(), ContractError> {
+    if channel.order != IbcOrder::Ordered {
+        return Err(ContractError::InvalidChannel {
+            msg: "Channel must be ordered".to_string(),
+        });
+    }
+    let versions = std::iter::once(&channel.version).chain(&channel.counterparty_version);
+    for version in versions {
+        if version != IBC_VERSION {
+            return Err(ContractError::InvalidChannel {
+                msg: format!("Version must be {}", IBC_VERSION),
+            });
+        }
+    }
+    Ok(())
+}
+
+pub fn ibc_channel_connect(
+    deps: DepsMut,
+    _env: Env,
+    channel: IbcChannel,
+) -> StdResult<IbcBasicResponse> {
+    let channel_id = channel.endpoint.channel_id;
+    let info = ChannelInfo {
+        channel_id: channel_id.clone(),
+        counterparty_port_id: channel.counterparty_endpoint.port_id,
+        counterparty_channel_id: channel.counterparty_endpoint.channel_id,
+        connection_id: channel.connection_id,
+    };
+    CHANNELS.save(deps.storage, &channel_id, &info)?;
+    Ok(IbcBasicResponse::new()
+        .add_attribute("action", "ibc_connect")
+        .add_attribute("channel_id", channel_id))
+}
+
+pub fn ibc_channel_close(
+    deps: DepsMut,
+    _env: Env,
+    channel: IbcChannel,
+) -> StdResult<IbcBasicResponse> {
+    let channel_id = channel.endpoint.channel_id;
+    CHANNELS.remove(deps.storage, &channel_id);
+    Ok(IbcBasicResponse::new()
+        .add_attribute("action", "ibc_close")
+        .add_attribute("channel_id", channel_id))
+}
+
+/// Runs the packet as a message from the contract to itself. The execution is
+/// a submessage, so when it fails all of its writes are reverted, including
+/// the circuit breaker counts, and `reply` acknowledges and records the error.
+/// A packet that times out was never run here.
+pub fn ibc_packet_receive(
+    deps: DepsMut,
+    env: Env,
+    packet: IbcPacket,
+) -> StdResult<IbcReceiveResponse> {
+    let res = IbcReceiveResponse::new()
+        .add_attribute("action", "ibc_receive")
+        .add_attribute("channel_id", &packet.dest.channel_id);
+    match packet_msg(deps.as_ref(), &packet.data) {
+        Ok(msg) => {
+            let pending = PendingPacket {
+                channel_id: packet.dest.channel_id.clone(),
+                sequence: packet.sequence,
+            };
+            PENDING_PACKET.save(deps.storage, &pending)?;
+            let msg = WasmMsg::Execute {
+                contract_addr: env.contract.address.into(),
+                msg: to_binary(&msg)?,
+                funds: vec![],
+            };
+            // replaced by the data of the reply
+            Ok(res
+                .set_ack(to_binary(&StackAck::Result(Binary::default()))?)
+                .add_submessage(SubMsg::reply_always(msg, PACKET_REPLY_ID)))
+        }
+        Err(err) => Ok(res
+            .set_ack(to_binary(&StackAck::Error(err.to_string()))?)
+            .add_attribute("success", "false")
+            .add_attribute("error_code", err.code().to_string())),
+    }
+}
+
+/// The execution of a packet has the contract as its sender, which passes
+/// every allowlist in council mode, where the contract is its own owner. So
+/// packets are refused while the allowlist they would have to pass is set.
+fn packet_msg(deps: Deps, data: &Binary) -> Result<ExecuteMsg, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    match from_slice(data)? {
+        PacketMsg::Push { .. } if config.pushers.is_some() => Err(ContractError::Unauthorized {}),
+        PacketMsg::Pop {} if config.poppers.is_some() => Err(ContractError::Unauthorized {}),
+        PacketMsg::Push { value } => Ok(ExecuteMsg::Push {
+            value,
+            beneficiary: None,
+            stack: None,
+        }),
+        PacketMsg::Pop {} => Ok(ExecuteMsg::Pop { stack: None }),
+    }
+}
+
+/// Acknowledges a packet with the outcome of its execution. The data set here
+/// replaces the acknowledgement of `ibc_packet_receive`. Failures are kept for
+/// the FailedDeliveries query.
+pub fn reply(deps: DepsMut, env: Env, reply: Reply) -> StdResult<Response> {
+    if reply.id != PACKET_REPLY_ID {
+        return Err(StdError::generic_err(format!(
+            "Unknown reply id {}",
+            reply.id
+        )));
+    }
+    let packet = PENDING_PACKET.load(deps.storage)?;
+    PENDING_PACKET.remove(deps.storage);
+    match reply.result {
+        ContractResult::Ok(res) => {
+            // the data is a MsgExecuteContractResponse with the data of the
+            // execution in field 1
+            let data = match res.data {
+                Some(data) => decode_bytes_field(&data, 1)?,
+                None => vec![],
+            };
+            Ok(Response::new()
+                .set_data(to_binary(&StackAck::Result(data.into()))?)
+                .add_attribute("action", "ibc_reply")
+                .add_attribute("success", "true"))
+        }
+        ContractResult::Err(err) => {
+            let code = ContractError::code_of_message(&err);
+            let delivery =
+                record_failed_delivery(deps.storage, &env.block, packet, err.clone(), code)?;
+            Ok(Response::new()
+                .set_data(to_binary(&StackAck::Error(err.clone()))?)
+                .add_attribute("action", "ibc_reply")
+                .add_attribute("success", "false")
+                .add_attribute("delivery_id", delivery.id.to_string())
+                .add_attribute("channel_id", delivery.channel_id)
+                .add_attribute("packet_sequence", delivery.sequence.to_string())
+                .add_attribute("error", err)
+                .add_attributes(code.map(|code| ("error_code", code.to_string()))))
+        }
+    }
+}
+
+/// This contract sends no packets, so there is nothing to acknowledge
+pub fn ibc_packet_ack(
+    _deps: DepsMut,
+    _env: Env,
+    _ack: IbcAcknowledgementWithPacket,
+) -> StdResult<IbcBasicResponse> {
+    Ok(IbcBasicResponse::new().add_attribute("action", "ibc_ack"))
+}
+
+/// This contract sends no packets, so there is nothing to roll back
+pub fn ibc_packet_timeout(
+    _deps: DepsMut,
+    _env: Env,
+    _packet: IbcPacket,
+) -> StdResult<IbcBasicResponse> {
+    Ok(IbcBasicResponse::new().add_attribute("action", "ibc_timeout"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::contract::{instantiate, query};
+    use crate::msg::{FailedDeliveriesResponse, InstantiateMsg, QueryMsg};
+    use cosmwasm_std::testing::{mock_dependencies, mock_env, mock_info};
+
+    #[test]
+    fn failed_packet_is_recorded() {
+        let mut deps = mock_dependencies(&[]);
+        let msg: InstantiateMsg = from_slice(b"{}").unwrap();
+        instantiate(deps.as_mut(), mock_env(), mock_info("creator", &[]), msg).unwrap();
+        let pending = PendingPacket {
+            channel_id: "channel-1".to_string(),
+            sequence: 7,
+        };
+        PENDING_PACKET.save(&mut deps.storage, &pending).unwrap();
+
+        let failed = Reply {
+            id: PACKET_REPLY_ID,
+            result: ContractResult::Err("[7] Stack is empty: execute wasm contract failed".into()),
+        };
+        let res = reply(deps.as_mut(), mock_env(), failed).unwrap();
+        let ack: StackAck = from_slice(&res.data.unwrap()).unwrap();
+        assert!(matches!(ack, StackAck::Error(_)));
+        let code = res.attributes.iter().find(|attr| attr.key == "error_code");
+        assert_eq!(code.unwrap().value, "7");
+        assert!(PENDING_PACKET.may_load(&deps.storage).unwrap().is_none());
+
+        let query_msg = QueryMsg::FailedDeliveries { page: None };
+        let res = query(deps.as_ref(), mock_env(), query_msg).unwrap();
+        let res: FailedDeliveriesResponse = from_slice(&res).unwrap();
+        assert_eq!(res.deliveries.len(), 1);
+        let delivery = &res.deliveries[0];
+        assert_eq!(delivery.channel_id, "channel-1");
+        assert_eq!(delivery.sequence, 7);
+        assert_eq!(delivery.error_code, Some(7));
+    }
+}
+
+#[cfg(all(target_arch = "wasm32", not(feature = "library")))]
+mod wasm {
+    use cosmwasm_std::{
+        do_ibc_channel_close, do_ibc_channel_connect, do_ibc_channel_open, do_ibc_packet_ack,
+        do_ibc_packet_receive, do_ibc_packet_timeout, do_reply,
+    };
+
+    #[no_mangle]
+    extern "C" fn ibc_channel_open(env_ptr: u32, msg_ptr: u32) -> u32 {
+        do_ibc_channel_open(&super::ibc_channel_open, env_ptr, msg_ptr)
+    }
+
+    #[no_mangle]
+    extern "C" fn ibc_channel_connect(env_ptr: u32, msg_ptr: u32) -> u32 {
+        do_ibc_channel_connect(&super::ibc_channel_connect, env_ptr, msg_ptr)
+    }
+
+    #[no_mangle]
+    extern "C" fn ibc_channel_close(env_ptr: u32, msg_ptr: u32) -> u32 {
+        do_ibc_channel_close(&super::ibc_channel_close, env_ptr, msg_ptr)
+    }
+
+    #[no_mangle]
+    extern "C" fn ibc_packet_receive(env_ptr: u32, msg_ptr: u32) -> u32 {
+        do_ibc_packet_receive(&super::ibc_packet_receive, env_ptr, msg_ptr)
+    }
+
+    #[no_mangle]
+    extern "C" fn ibc_packet_ack(env_ptr: u32, msg_ptr: u32) -> u32 {
+        do_ibc_packet_ack(&super::ibc_packet_ack, env_ptr, msg_ptr)
+    }
+
+    #[no_mangle]
+    extern "C" fn ibc_packet_timeout(env_ptr: u32, msg_ptr: u32) -> u32 {
+        do_ibc_packet_timeout(&super::ibc_packet_timeout, env_ptr, msg_ptr)
+    }
+
+    #[no_mangle]
+    extern "C" fn reply(env_ptr: u32, msg_ptr: u32) -> u32 {
+        do_reply(&super::reply, env_ptr, msg_ptr)
+    }
+}
@@ -1,5 +1,27 @@
+#[cfg(feature = "contract")]
 pub mod contract;
+pub mod error;
+pub mod helpers;
+#[cfg(feature = "ibc")]
+pub mod ibc;
 pub mod msg;
+#[cfg(feature = "contract")]
+pub mod nois;
+#[cfg(feature = "contract")]
+pub mod proto;
+#[cfg(feature = "contract")]
+pub mod rng;
+#[cfg(feature = "contract")]
+pub mod state;
 
-#[cfg(target_arch = "wasm32")]
-cosmwasm_std::create_entry_points!(contract);
+#[cfg(all(target_arch = "wasm32", feature = "contract", not(feature = "library")))]
+cosmwasm_std::create_entry_points_with_migration!(contract);
+
+// the macro has no sudo entry point
+#[cfg(all(target_arch = "wasm32", feature = "contract", not(feature = "library")))]
+mod wasm {
+    #[no_mangle]
+    extern "C" fn sudo(env_ptr: u32, msg_ptr: u32) -> u32 {
+        cosmwasm_std::do_sudo(&super::contract::sudo, env_ptr, msg_ptr)
+    }
+}
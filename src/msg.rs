@@ -1,8 +1,1062 @@
 use schemars::JsonSchema;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use cosmwasm_std::{Addr, Binary, Coin, Decimal, Order, Timestamp, Uint128};
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct InstantiateMsg {
+    /// Pause the contract once more operations than this are executed in a
+    /// single block. Unlimited if unset.
+    #[serde(alias = "maxOpsPerBlock")]
+    pub max_ops_per_block: Option<u32>,
+    /// Only accept Push from this kind of sender. Anyone may push if unset.
+    #[serde(alias = "pushRestriction")]
+    pub push_restriction: Option<CallerRestriction>,
+    /// Privacy mode: only commitments are pushed and values are revealed on
+    /// pop, see `ExecuteMsg::PushHidden`
+    #[serde(alias = "hiddenValues")]
+    pub hidden_values: Option<bool>,
+    /// How many of the latest pushed values are kept for `MovingAverage`.
+    /// Defaults to 32, at most 256.
+    #[serde(alias = "movingAverageWindow")]
+    pub moving_average_window: Option<u32>,
+    /// Maximum number of items in the stack, quarantined ones included.
+    /// Defaults to 256, at most 100000.
+    pub capacity: Option<u32>,
+    /// Address of the nois proxy that serves `ExecuteMsg::RequestRandomPop`.
+    /// Random pops are disabled if unset.
+    pub nois_proxy: Option<String>,
+    /// Lottery mode: every push costs a ticket, the fees go into a pot and
+    /// random pops pay the pot to the pusher of the popped item. Plain and
+    /// weighted pops are disabled. Requires `nois_proxy`.
+    pub lottery: Option<LotteryConfig>,
+    /// Pop auction mode: the right to execute each pop goes to the highest
+    /// bidder, see `ExecuteMsg::BidForPop`. Can't be combined with `nois_proxy`
+    /// or hidden values mode.
+    pub pop_auction: Option<PopAuctionConfig>,
+    /// Work queue mode: seconds an `ExecuteMsg::ClaimItem` lock lasts before
+    /// the item can be claimed again. Claims are disabled if unset. Can't be
+    /// combined with hidden values, lottery or pop auction mode.
+    pub claim_timeout: Option<u64>,
+    /// Hands the owner rights to a council instead of the sender. Owner only
+    /// messages then have to be proposed and approved by members holding at
+    /// least the threshold weight, see `ExecuteMsg::Propose`.
+    pub council: Option<CouncilConfig>,
+    /// Encoding of the data returned by execute messages. Defaults to JSON.
+    pub data_encoding: Option<DataEncoding>,
+    /// Wraps the item returned by pops in a protobuf `Any` with this type URL,
+    /// its value encoded as configured by `data_encoding`. Unwrapped if unset.
+    pub pop_type_url: Option<String>,
+    /// How many items `ExecuteMsg::Clear` and `ContinueClear` delete per
+    /// transaction. Defaults to 50.
+    pub clear_chunk_size: Option<u32>,
+    /// Only these addresses and the owner may push. Anyone may push if unset.
+    pub pushers: Option<Vec<String>>,
+    /// Only these addresses and the owner may pop, in any of the ways to pop.
+    /// Anyone may pop if unset.
+    pub poppers: Option<Vec<String>>,
+    /// Contracts `ExecuteMsg::PopInto` may call. Any contract but this one if
+    /// unset.
+    pub pop_into_targets: Option<Vec<String>>,
+    /// Size limits on execute messages, see `Limits` for the defaults
+    pub limits: Option<Limits>,
+    /// Compressed secp256k1 public key that has to sign the destructive owner
+    /// messages, see `ExecuteMsg::AdminOpWithSig`. The owner sends them
+    /// directly if unset.
+    pub cold_admin_key: Option<Binary>,
+}
+
+/// What the cold admin key signs for `ExecuteMsg::AdminOpWithSig`. The chain
+/// and contract keep a signature from being replayed elsewhere.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct AdminOpSignDoc {
+    pub chain_id: String,
+    pub contract: String,
+    pub nonce: u64,
+    pub op: ExecuteMsg,
+}
+
+/// Upper bounds on the size of execute messages, so a single message can't
+/// use up the resources of a node. Unset ones take the default.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema, Default)]
+pub struct Limits {
+    /// Ops in an `ExecuteMsg::Batch`. Defaults to 20.
+    pub batch_ops: Option<u32>,
+    /// Values of a PushMany and count of a PopMany. Defaults to 50.
+    pub many_items: Option<u32>,
+    /// Bytes of a string or bytes payload, a reference key or a work result.
+    /// Defaults to 1024.
+    pub payload_bytes: Option<u32>,
+    /// Bytes of a PopInto message template. Defaults to 4096.
+    pub template_bytes: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DataEncoding {
+    Json,
+    /// bincode 1.x with its default options, cheaper to decode for contracts.
+    /// Payloads are encoded as `TaggedPayload` there.
+    Bincode,
+}
+
+// deriving it with #[default] needs Rust 1.62
+#[allow(clippy::derivable_impls)]
+impl Default for DataEncoding {
+    fn default() -> Self {
+        DataEncoding::Json
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct CouncilConfig {
+    pub members: Vec<CouncilMember>,
+    /// Total weight of the votes a proposal needs to be executed
+    pub threshold: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct CouncilMember {
+    pub addr: String,
+    pub weight: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct LotteryConfig {
+    pub denom: String,
+    /// Exact amount every push has to pay
+    pub ticket_price: Uint128,
+}
+
+/// Highest bid of the running pop auction
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Bid {
+    pub bidder: Addr,
+    pub amount: Uint128,
+    /// Bidding closes at this time, then only the bidder may pop until the
+    /// deadline, see `PopAuctionConfig::pop_deadline`
+    pub ends_at: Timestamp,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PopAuctionConfig {
+    pub denom: String,
+    /// How long bidding stays open after the first bid
+    pub bidding_seconds: u64,
+    /// How long the highest bidder has to pop once bidding closed. After that
+    /// the bid goes to the owner and bidding opens again. Defaults to
+    /// `bidding_seconds`.
+    #[serde(default)]
+    pub pop_seconds: Option<u64>,
+}
+
+impl PopAuctionConfig {
+    /// When the winner of `bid` loses the right to pop
+    pub fn pop_deadline(&self, bid: &Bid) -> Timestamp {
+        bid.ends_at
+            .plus_seconds(self.pop_seconds.unwrap_or(self.bidding_seconds))
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum CallerRestriction {
+    /// Sender must be a smart contract
+    #[serde(alias = "contractsOnly")]
+    ContractsOnly,
+    /// Sender must be an externally owned account
+    #[serde(alias = "accountsOnly")]
+    AccountsOnly,
+}
+
+/// Pagination parameters shared by all listing queries
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema, Default)]
+pub struct PageRequest {
+    /// Only list entries after this key, in the requested order
+    #[serde(alias = "startAfter")]
+    pub start_after: Option<u64>,
+    /// Defaults to 10, at most 30
+    pub limit: Option<u32>,
+    /// Defaults to ascending
+    pub order: Option<Order>,
+}
+
+/// Returned next to each page of a listing query
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PageResponse {
+    /// Pass this as `start_after` to get the next page. None on the last page.
+    pub next_key: Option<u64>,
+    /// Number of entries in the whole listing
+    pub total: u64,
+}
+
+// we store one entry for each item in the stack
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Item {
+    pub value: i32,
+    // who pushed the item and when. Unset for items pushed before this was recorded.
+    #[serde(default)]
+    pub sender: Option<Addr>,
+    #[serde(default)]
+    pub height: Option<u64>,
+    #[serde(default)]
+    pub time: Option<Timestamp>,
+    // funds held for the beneficiary until the item is popped or claimed
+    #[serde(default)]
+    pub escrow: Option<Escrow>,
+    // the worker processing the item in work queue mode
+    #[serde(default)]
+    pub claim: Option<WorkClaim>,
+    // data stored by another contract that the item stands for
+    #[serde(default)]
+    pub reference: Option<ItemRef>,
+    // a string or bytes payload, value is 0 then. Unset for integers.
+    #[serde(
+        default,
+        serialize_with = "serialize_payload",
+        deserialize_with = "deserialize_payload"
+    )]
+    pub payload: Option<Payload>,
+    // how many active items had a greater value at the last SudoMsg::Reducer
+    // run. Unset for string and bytes payloads and newer items.
+    #[serde(default)]
+    pub exceeded_by: Option<u32>,
+}
+
+impl Item {
+    /// The value counted by Sum and the other statistics, None for string
+    /// and bytes payloads
+    pub fn numeric(&self) -> Option<i32> {
+        match self.payload {
+            Some(_) => None,
+            None => Some(self.value),
+        }
+    }
+
+    /// The payload as pushed
+    pub fn payload(&self) -> Payload {
+        self.payload.clone().unwrap_or(Payload::Int(self.value))
+    }
+}
+
+/// Value of a pushed item. Integers and strings are given as plain JSON
+/// values, so `{"value": 5}` still pushes an integer, bytes as
+/// `{"bytes": "<base64>"}`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(untagged)]
+pub enum Payload {
+    Int(i32),
+    String(String),
+    Bytes { bytes: Binary },
+}
+
+/// `Payload` as encoded in bincode data. bincode writes no field names or
+/// types, so the variants of the untagged `Payload` would be impossible to
+/// tell apart there.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum TaggedPayload {
+    Int(i32),
+    String(String),
+    Bytes(Binary),
+}
+
+impl From<Payload> for TaggedPayload {
+    fn from(payload: Payload) -> Self {
+        match payload {
+            Payload::Int(value) => TaggedPayload::Int(value),
+            Payload::String(value) => TaggedPayload::String(value),
+            Payload::Bytes { bytes } => TaggedPayload::Bytes(bytes),
+        }
+    }
+}
+
+impl From<TaggedPayload> for Payload {
+    fn from(payload: TaggedPayload) -> Self {
+        match payload {
+            TaggedPayload::Int(value) => Payload::Int(value),
+            TaggedPayload::String(value) => Payload::String(value),
+            TaggedPayload::Bytes(bytes) => Payload::Bytes { bytes },
+        }
+    }
+}
+
+// JSON, as stored and in queries, keeps the untagged form. Binary formats
+// like bincode get the tagged one.
+fn serialize_payload<S: Serializer>(
+    payload: &Option<Payload>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    if serializer.is_human_readable() {
+        payload.serialize(serializer)
+    } else {
+        payload
+            .clone()
+            .map(TaggedPayload::from)
+            .serialize(serializer)
+    }
+}
+
+fn deserialize_payload<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Payload>, D::Error> {
+    if deserializer.is_human_readable() {
+        Option::<Payload>::deserialize(deserializer)
+    } else {
+        Ok(Option::<TaggedPayload>::deserialize(deserializer)?.map(Payload::from))
+    }
+}
+
+/// Points at data stored by another contract, so large payloads can be
+/// ordered by the stack without storing them twice
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ItemRef {
+    pub contract: Addr,
+    /// Raw storage key in the contract, or a smart query if `smart` is set
+    pub key: Binary,
+    #[serde(default)]
+    pub smart: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Escrow {
+    pub beneficiary: Addr,
+    pub funds: Vec<Coin>,
+}
+
+/// A lock on an item. Once it expires the item can be popped or claimed again.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct WorkClaim {
+    pub worker: Addr,
+    pub expires_at: Timestamp,
+}
+
+// in hidden values mode we only store sha256(value || salt) until the pop
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct SealedItem {
+    pub commitment: Binary,
+    #[serde(default)]
+    pub sender: Option<Addr>,
+    #[serde(default)]
+    pub height: Option<u64>,
+    #[serde(default)]
+    pub time: Option<Timestamp>,
+}
+
+/// Sent by the proxy once the beacon for a job is published
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct NoisCallback {
+    pub job_id: String,
+    pub published: Timestamp,
+    /// 32 bytes, hex encoded
+    pub randomness: String,
+}
+
+// camelCase aliases keep clients of the earlier camelCase API working. They
+// only cover the messages and fields that API had, anything added since is
+// snake_case only.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ExecuteMsg {
+    // Push will add some value to the end of list. With a beneficiary, the
+    // funds sent along are held by the item and go to the beneficiary when
+    // the item is popped or claimed. Pushes to the named stack if given,
+    // named stacks take no beneficiary. Only integer values count in Sum
+    // and the other statistics.
+    #[serde(alias = "enqueue")]
+    Push {
+        value: Payload,
+        beneficiary: Option<String>,
+        stack: Option<String>,
+    },
+    // PushRef adds an item standing for data stored by another contract, at
+    // the raw storage key or returned by the smart query in key. The value
+    // counts like the value of any push.
+    PushRef {
+        value: i32,
+        contract: String,
+        key: Binary,
+        smart: Option<bool>,
+    },
+    // Pop will remove value from end of the list. Pops from the named stack
+    // if given.
+    Pop {
+        stack: Option<String>,
+    },
+    // Dequeue removes the first value of the list instead, like Dequeue of
+    // the queue example
+    Dequeue {},
+    // PopInto pops like Pop and executes msg_template on contract, with every
+    // {{value}} in it replaced by the popped payload as JSON, e.g.
+    // {"take":{"value":{{value}}}}. The template must be valid JSON with null
+    // in place of {{value}}. If that call fails, the pop is rolled back with
+    // it. Fails if there is nothing to pop.
+    PopInto {
+        contract: String,
+        msg_template: String,
+    },
+    // Pushes all values in order like as many Push messages, in one
+    // transaction. Not available in lottery mode.
+    PushMany {
+        values: Vec<Payload>,
+    },
+    // Pops count items like as many Pop messages, with an event for each.
+    // Fails without popping anything if fewer items are there.
+    PopMany {
+        count: u32,
+    },
+    // PushHidden adds sha256(value || salt) to the end of the list, where value
+    // is encoded as 4 big-endian bytes. Only available in hidden values mode.
+    #[serde(alias = "pushHidden")]
+    PushHidden {
+        commitment: Binary,
+    },
+    // RevealPop removes the last commitment from the list if value and salt
+    // match it. Replaces Pop in hidden values mode.
+    #[serde(alias = "revealPop")]
+    RevealPop {
+        value: i32,
+        salt: Binary,
+    },
+    // Unpause the contract after the circuit breaker tripped (owner only)
+    #[serde(alias = "resetCircuitBreaker")]
+    ResetCircuitBreaker {},
+    // Set an item aside so pops skip it and aggregates ignore it (owner only)
+    #[serde(alias = "quarantineItem")]
+    QuarantineItem {
+        index: u64,
+    },
+    // Put a quarantined item back into the stack (owner only)
+    #[serde(alias = "releaseItem")]
+    ReleaseItem {
+        index: u64,
+    },
+    // Delete a quarantined item for good (owner only)
+    #[serde(alias = "removeItem")]
+    RemoveItem {
+        index: u64,
+    },
+    // Batch executes all ops in order, failing as a whole if one of them fails.
+    // Batches can't be nested.
+    Batch {
+        ops: Vec<ExecuteMsg>,
+    },
+    // Asks the nois proxy for randomness to pop a uniformly random active item
+    // with. Any funds sent pay the proxy fee and must be a single coin. If
+    // weighted, the chance of each item is proportional to its value, and
+    // items of 0 or less are never picked.
+    RequestRandomPop {
+        weighted: Option<bool>,
+    },
+    // Bids the funds sent along for the right to execute the next pop. The
+    // first bid opens bidding for the configured time, and outbid bidders
+    // are refunded right away. The winner gets the funds the popped item
+    // holds, if any. A winner who doesn't pop in time loses the bid to the
+    // owner, and the next bid opens bidding again. Only available in pop
+    // auction mode.
+    BidForPop {},
+    // Locks the last unclaimed active item for the sender to work on, for the
+    // configured claim timeout. Only available in work queue mode.
+    ClaimItem {},
+    // Removes an item claimed by the sender, returning result as data
+    CompleteItem {
+        index: u64,
+        result: Binary,
+    },
+    // Releases the funds of an item to its beneficiary, leaving the item in
+    // the stack (beneficiary only)
+    Claim {
+        index: u64,
+    },
+    // Deletes all items, quarantined ones included, paying out their funds.
+    // Big stacks are deleted in chunks, see ContinueClear. Pushes fail until
+    // the stack is empty (owner only).
+    Clear {},
+    // Deletes the next chunk of a clear that didn't finish. Anyone may call it.
+    ContinueClear {},
+    // Reorders the active items with a PRNG seeded by sha256(seed), so the
+    // same seed and stack always give the same order. Fails while an item is
+    // claimed or holds funds (owner only).
+    Shuffle {
+        seed: Binary,
+    },
+    // Hands the owner rights to another address (owner only)
+    UpdateOwner {
+        owner: String,
+    },
+    // Replaces the allowlists of addresses that may push and pop, unset
+    // lists allow anyone (owner only)
+    UpdatePermissions {
+        pushers: Option<Vec<String>>,
+        poppers: Option<Vec<String>>,
+    },
+    // Replaces the contracts PopInto may call, unset allows any contract but
+    // this one (owner only)
+    UpdatePopIntoTargets {
+        targets: Option<Vec<String>>,
+    },
+    // Replaces the cold admin key, unset lets the owner send destructive
+    // messages directly again (owner only, signed if a cold key is set)
+    UpdateColdAdminKey {
+        key: Option<Binary>,
+    },
+    // Executes op as the owner if signature is a secp256k1 signature by the
+    // cold admin key pubkey of the sha256 hash of the JSON AdminOpSignDoc.
+    // nonce must be one more than that of the last admin op. Anyone may
+    // submit it. With a cold admin key set, Clear, Shuffle, RemoveItem and
+    // the update messages of owner and permissions only run this way.
+    AdminOpWithSig {
+        op: Box<ExecuteMsg>,
+        pubkey: Binary,
+        signature: Binary,
+        nonce: u64,
+    },
+    // Rebuilds the stats from a scan over all items, to check on or repair
+    // the running aggregates (owner only)
+    Recompute {},
+    // Proposes an owner message to the council. Counts as a vote of the
+    // proposer. Returns the proposal id as data (council members only).
+    Propose {
+        msg: Box<ExecuteMsg>,
+    },
+    // Votes for a proposal with the weight of the sender (council members only)
+    Vote {
+        proposal_id: u64,
+    },
+    // Runs a proposal that reached the threshold, once. Anyone may execute it.
+    Execute {
+        proposal_id: u64,
+    },
+    // Adds an empty named stack. Named stacks hold plain items, so they can't
+    // be created with hidden values, a lottery or a pop auction (owner only).
+    CreateStack {
+        name: String,
+    },
+    // Deletes a named stack, which must be empty (owner only)
+    RemoveStack {
+        name: String,
+    },
+    // The randomness callback of the nois proxy (proxy only)
+    NoisReceive {
+        callback: NoisCallback,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum QueryMsg {
+    // how many items are in the stack, or in the named stack if given
+    #[serde(alias = "size")]
+    Count {
+        stack: Option<String>,
+    },
+    // total of all values in the stack, or in the named stack if given
+    Sum {
+        stack: Option<String>,
+    },
+
+    // a page of the active items with their indexes. Order defaults to
+    // ascending, pass descending to start from the top of the stack.
+    List {
+        start_after: Option<u64>,
+        limit: Option<u32>,
+        order: Option<Order>,
+    },
+    // the item indexes below and from 0x20, what List used to return. Only
+    // kept to debug range queries, don't use it for real data.
+    ListBuckets {
+        order: Option<Order>,
+    },
+    // whether the circuit breaker has paused the contract
+    #[serde(alias = "circuitBreaker")]
+    CircuitBreaker {},
+    // all items currently in quarantine
+    #[serde(alias = "quarantinedItems")]
+    QuarantinedItems {
+        page: Option<PageRequest>,
+    },
+    // average of the latest n pushed values, whether popped or not
+    #[serde(alias = "movingAverage")]
+    MovingAverage {
+        n: u32,
+    },
+    // count, sum and open/high/low/close of the values pushed in each
+    // bucket_seconds wide time bucket between start and end (unix seconds)
+    #[serde(alias = "bucketedSum")]
+    BucketedSum {
+        #[serde(alias = "bucketSeconds")]
+        bucket_seconds: u64,
+        start: u64,
+        end: u64,
+    },
+    // value at the given percentile (0 to 1) of the items in the stack
+    Percentile {
+        p: Decimal,
+    },
+    // mean, variance and skewness of the items in the stack
+    Distribution {},
+    // how many items in the stack hold each of the given values
+    #[serde(alias = "countByValue")]
+    CountByValue {
+        values: Vec<i32>,
+    },
+    // items pushed between the heights from and to (both inclusive), ordered
+    // by height. The page cursor is the index of the last item returned.
+    #[serde(alias = "itemsByHeight")]
+    ItemsByHeight {
+        from: u64,
+        to: u64,
+        page: Option<PageRequest>,
+    },
+    // items pushed between the block times from and to (both inclusive),
+    // ordered by time. Paged like ItemsByHeight.
+    #[serde(alias = "itemsByTime")]
+    ItemsByTime {
+        from: Timestamp,
+        to: Timestamp,
+        page: Option<PageRequest>,
+    },
+    // the first active item in stack order, with its metadata. That is the
+    // one pushed first unless the stack was shuffled. Null if there is none.
+    Oldest {},
+    // the last active item in stack order, with its metadata. Null if there
+    // is none.
+    Newest {},
+    // number of active items at the end of each of the given heights
+    #[serde(alias = "depthHistory")]
+    DepthHistory {
+        heights: Vec<u64>,
+    },
+    // push and pop counts per block, keyed by height
+    #[serde(alias = "blockMetrics")]
+    BlockMetrics {
+        page: Option<PageRequest>,
+    },
+    // active items in push order, starting after the item the cursor points at
+    // With resolve, references are resolved by querying the contracts they
+    // point at.
+    Iterate {
+        cursor: Option<Binary>,
+        limit: Option<u32>,
+        resolve: Option<bool>,
+    },
+    // operations in sequence order, starting after the cursor. Only the
+    // latest 1000 are kept, a jump in seq means older ones were dropped.
+    OpsFeed {
+        cursor: Option<u64>,
+        limit: Option<u32>,
+    },
+    // emptiness, item count and free slots in one call
+    Status {},
+    // ticket price and current pot. Null if lottery mode is off.
+    Lottery {},
+    // the highest bid for the next pop. Null if pop auction mode is off.
+    PopAuction {},
+    // raw storage keys of the items, config and counters, for reading them
+    // with QueryRawContractState and verifying the result with a proof
+    #[serde(alias = "keyLayout")]
+    KeyLayout {},
+    // funds held by the contract and what they are owed for
+    Treasury {},
+    // members and threshold of the council. Null if there is none.
+    Council {},
+    Proposal {
+        proposal_id: u64,
+    },
+    // runs several queries at once, returning their results in order.
+    // Can't be nested.
+    Multi {
+        queries: Vec<QueryMsg>,
+    },
+    // all named stacks with their sizes, by name
+    ListStacks {},
+    // count, sum, min and max of the stack, kept up to date on every change
+    Stats {},
+    // the open IBC channels, by channel id
+    ListChannels {},
+    // IBC packets whose execution failed, by id. Only the latest 100 are
+    // kept.
+    FailedDeliveries {
+        page: Option<PageRequest>,
+    },
+    // the payload of the item at depth, counted from the top of the stack.
+    // Depth 0 (the default) is the item the next pop would return.
+    Peek {
+        depth: Option<u32>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct CountResponse {
+    pub count: u32,
+}
+
+/// An open IBC channel, see `crate::ibc`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ChannelInfo {
+    pub channel_id: String,
+    pub counterparty_port_id: String,
+    pub counterparty_channel_id: String,
+    pub connection_id: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ListChannelsResponse {
+    pub channels: Vec<ChannelInfo>,
+}
+
+/// An IBC packet whose execution failed and was acknowledged with the error
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct FailedDelivery {
+    pub id: u64,
+    pub channel_id: String,
+    pub sequence: u64,
+    /// The error as the chain reported it
+    pub error: String,
+    /// The `ContractError` code found in the error, if any
+    pub error_code: Option<u32>,
+    pub height: u64,
+    pub time: Timestamp,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct FailedDeliveriesResponse {
+    pub deliveries: Vec<FailedDelivery>,
+    pub page: PageResponse,
+}
+
+/// Data of the packets a counterparty sends over IBC
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PacketMsg {
+    Push { value: Payload },
+    Pop {},
+}
+
+/// Acknowledgement of a packet. A result carries the data the push or pop
+/// returned, for a pop the popped item.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum StackAck {
+    Result(Binary),
+    Error(String),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PeekResponse {
+    pub payload: Option<Payload>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ListStacksResponse {
+    pub stacks: Vec<StackInfo>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct StackInfo {
+    pub name: String,
+    pub count: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct SumResponse {
+    pub sum: i32,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+pub struct ListResponse {
+    pub items: Vec<ItemResponse>,
+    pub page: PageResponse,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ListBucketsResponse {
+    /// List an empty range, both bounded
+    pub empty: Vec<u32>,
+    /// List all IDs lower than 0x20
+    pub early: Vec<u32>,
+    /// List all IDs starting from 0x20
+    pub late: Vec<u32>,
+}
+
+/// Data returned by PopMany
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PopManyResponse {
+    /// The data each pop returned, in pop order
+    pub items: Vec<Binary>,
+}
+
+/// Data returned by Batch
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct BatchResponse {
+    /// The data each op returned, in op order
+    pub results: Vec<Option<Binary>>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct QuarantinedItemsResponse {
+    pub items: Vec<QuarantinedItem>,
+    pub page: PageResponse,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct QuarantinedItem {
+    pub index: u64,
+    /// The stored item, encoded like the data returned by Pop
+    pub data: Binary,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct MovingAverageResponse {
+    /// Number of values averaged, less than requested if fewer were pushed
+    pub count: u32,
+    pub sum: i64,
+    /// sum / count, rounded towards zero. 0 if no values were pushed.
+    pub average: i32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct BucketedSumResponse {
+    /// Buckets without items are not listed
+    pub buckets: Vec<Bucket>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Bucket {
+    /// Inclusive start of the bucket in unix seconds
+    pub start: u64,
+    pub count: u32,
+    pub sum: i64,
+    /// First and last value pushed in the bucket
+    pub open: i32,
+    pub close: i32,
+    pub high: i32,
+    pub low: i32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PercentileResponse {
+    /// None if the stack is empty
+    pub value: Option<i32>,
+}
+
+/// Fixed-point statistics in millionths, all rounded towards zero.
+/// Variance and skewness are those of the population, not of a sample.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct DistributionResponse {
+    pub count: u32,
+    pub mean_micro: i64,
+    pub variance_micro: Uint128,
+    /// 0 if all values are equal
+    pub skewness_micro: i64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct CountByValueResponse {
+    /// One entry per requested value, in request order
+    pub counts: Vec<ValueCount>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ValueCount {
+    pub value: i32,
+    pub count: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ItemsResponse {
+    pub items: Vec<ItemResponse>,
+    pub page: PageResponse,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ItemResponse {
+    pub index: u64,
+    pub item: Item,
+    /// The data the reference of the item points at, if it was resolved.
+    /// Empty if a raw key is not set in the other contract.
+    pub resolved: Option<Binary>,
+}
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
-pub struct InstantiateMsg {}
+pub struct IterateResponse {
+    pub items: Vec<ItemResponse>,
+    /// Opaque token to pass to the next Iterate call. None once the end is reached.
+    pub cursor: Option<Binary>,
+}
 
-// #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
-// pub struct MigrateMsg {}
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct DepthHistoryResponse {
+    /// One entry per requested height, in request order
+    pub depths: Vec<DepthAt>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct DepthAt {
+    pub height: u64,
+    pub depth: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct BlockMetricsResponse {
+    /// Blocks without pushes or pops are not listed
+    pub blocks: Vec<BlockMetricsEntry>,
+    pub page: PageResponse,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct BlockMetricsEntry {
+    pub height: u64,
+    pub pushes: u32,
+    pub pops: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct MultiResponse {
+    /// The JSON response of each query, in query order
+    pub results: Vec<Binary>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct CircuitBreakerResponse {
+    pub paused: bool,
+    pub max_ops_per_block: Option<u32>,
+    /// Operations executed so far in the current block
+    pub ops_this_block: u32,
+}
+
+/// One entry of the operations feed, keyed by its op sequence
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct OpRecord {
+    pub seq: u64,
+    /// Same as the action attribute of the operation
+    pub action: String,
+    /// The item the operation changed. None for operations on the whole stack.
+    pub index: Option<u64>,
+    /// The named stack the item is in, None for the main stack
+    #[serde(default)]
+    pub stack: Option<String>,
+    /// The item as stored after the operation, if it was created or edited
+    pub data: Option<Binary>,
+    pub height: u64,
+    pub time: Timestamp,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct OpsFeedResponse {
+    pub ops: Vec<OpRecord>,
+    /// The seq of the last operation returned, or the given cursor if there
+    /// were none. Pass it to the next call to continue.
+    pub cursor: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct StatusResponse {
+    /// Whether there is no active item left to pop
+    pub is_empty: bool,
+    /// Number of active items, like `QueryMsg::Count`
+    pub count: u32,
+    pub capacity: u32,
+    /// Pushes that fit before the stack is full. Quarantined items take up slots.
+    pub remaining: u32,
+    /// Whether a clear is still deleting items, see `ExecuteMsg::ContinueClear`
+    pub clearing: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct LotteryResponse {
+    pub denom: String,
+    pub ticket_price: Uint128,
+    /// Paid out in full by the next random pop that finds an item
+    pub pot: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PopAuctionResponse {
+    pub denom: String,
+    pub bidding_seconds: u64,
+    pub pop_seconds: u64,
+    /// None until someone bids for the next pop
+    pub highest_bid: Option<Bid>,
+    /// When the highest bidder loses the right to pop, once there is a bid
+    pub pop_deadline: Option<Timestamp>,
+}
+
+/// Funds by what they are held for. Liabilities are listed per denom, and
+/// empty if nothing is held for them.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct TreasuryResponse {
+    /// Native balances of the contract
+    pub balances: Vec<Coin>,
+    /// Lottery ticket fees in the pot, owed to the next random pop winner
+    pub push_fees: Vec<Coin>,
+    /// Funds items hold for their beneficiaries, quarantined items included
+    pub escrow: Vec<Coin>,
+    /// Highest bid of the running pop auction, owed to the owner or back to
+    /// the bidder
+    pub bids: Vec<Coin>,
+    /// Balances not owed to anyone, e.g. funds sent along by mistake
+    pub surplus: Vec<Coin>,
+}
+
+/// Raw storage keys, stable as long as `version` is the same. All values are
+/// JSON encoded.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct KeyLayoutResponse {
+    pub version: u32,
+    pub config: Binary,
+    /// Number of active items, a u32
+    pub depth: Binary,
+    /// Sequence number of the last operation, a u64
+    pub op_seq: Binary,
+    /// Items are stored at this prefix followed by their index as 8
+    /// big-endian bytes
+    pub item_prefix: Binary,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Proposal {
+    pub proposer: Addr,
+    /// Executed with the contract as sender
+    pub msg: ExecuteMsg,
+    /// Total weight of the members that voted for it
+    pub votes: u64,
+    pub executed: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ProposalResponse {
+    pub proposal: Proposal,
+    /// Whether it can be executed now
+    pub passed: bool,
+    pub voters: Vec<Addr>,
+}
+
+/// Messages chain governance can send to rewrite the whole stack, for
+/// operational recovery
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SudoMsg {
+    // Stores with every active integer item how many other active items have
+    // a greater value, see Item::exceeded_by
+    Reducer {},
+    // Removes the newer half of the active items, rounded down, and sends the
+    // funds they hold to recipient instead of their beneficiaries
+    StealHalf { recipient: String },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum MigrateMsg {
+    // Converts the state of the cosmwasm queue example, whose items are stored
+    // like ours used to be, see WideKeys. They keep their index, but pops take
+    // the newest item again instead of the oldest. The admin of the contract
+    // becomes its owner.
+    FromQueueExample {},
+    // Moves the items of a stack from before version 2 of the key layout from
+    // their one-byte keys to 8 byte indexes, see QueryMsg::KeyLayout
+    WideKeys {},
+    // Runs the migrations the version stored with the contract needs to get
+    // to this code. Fails if the stored version is newer than this code.
+    Upgrade {},
+}
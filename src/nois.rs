@@ -0,0 +1,35 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use cosmwasm_std::{StdError, StdResult};
+
+// The nois crates need a newer cosmwasm-std, so the parts of the proxy
+// interface we use are mirrored here.
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ProxyExecuteMsg {
+    // ask for the next beacon, delivered to NoisReceive with the same job id
+    GetNextRandomness { job_id: String },
+}
+
+/// Decodes the hex encoded randomness of a callback
+pub fn decode_randomness(hex: &str) -> StdResult<[u8; 32]> {
+    let invalid = || StdError::generic_err("Randomness must be 32 hex encoded bytes");
+    if hex.len() != 64 || !hex.is_ascii() {
+        return Err(invalid());
+    }
+    let mut randomness = [0u8; 32];
+    for (i, byte) in randomness.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).map_err(|_| invalid())?;
+    }
+    Ok(randomness)
+}
+
+/// Picks a number below `n` from the randomness. Reducing 64 bits modulo n
+/// is biased by at most n / 2^64, negligible for the ranges used here.
+pub fn int_below(randomness: &[u8; 32], n: u64) -> u64 {
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&randomness[..8]);
+    u64::from_be_bytes(bytes) % n
+}
@@ -0,0 +1,77 @@
+use cosmwasm_std::{StdError, StdResult};
+
+/// Protobuf encoding of `google.protobuf.Any { type_url, value }`. Written by
+/// hand since these two fields are all the contract ever needs to encode.
+pub fn encode_any(type_url: &str, value: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(type_url.len() + value.len() + 12);
+    encode_bytes_field(&mut buf, 1, type_url.as_bytes());
+    encode_bytes_field(&mut buf, 2, value);
+    buf
+}
+
+/// A length delimited field. Empty fields are left out, like proto3 does.
+fn encode_bytes_field(buf: &mut Vec<u8>, field: u32, bytes: &[u8]) {
+    if bytes.is_empty() {
+        return;
+    }
+    encode_varint(buf, u64::from(field << 3 | 2));
+    encode_varint(buf, bytes.len() as u64);
+    buf.extend_from_slice(bytes);
+}
+
+fn encode_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+/// Returns the bytes of a length delimited field, or nothing if the field is
+/// missing. Varint fields are skipped, any other wire type is an error.
+pub fn decode_bytes_field(buf: &[u8], field: u32) -> StdResult<Vec<u8>> {
+    let mut pos = 0;
+    while pos < buf.len() {
+        let key = decode_varint(buf, &mut pos)?;
+        match key & 7 {
+            0 => {
+                decode_varint(buf, &mut pos)?;
+            }
+            2 => {
+                let len = decode_varint(buf, &mut pos)? as usize;
+                let end = pos
+                    .checked_add(len)
+                    .filter(|end| *end <= buf.len())
+                    .ok_or_else(|| {
+                        StdError::parse_err("protobuf", "field ends after the buffer")
+                    })?;
+                if key >> 3 == u64::from(field) {
+                    return Ok(buf[pos..end].to_vec());
+                }
+                pos = end;
+            }
+            wire_type => {
+                return Err(StdError::parse_err(
+                    "protobuf",
+                    format!("unsupported wire type {}", wire_type),
+                ))
+            }
+        }
+    }
+    Ok(vec![])
+}
+
+fn decode_varint(buf: &[u8], pos: &mut usize) -> StdResult<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = *buf
+            .get(*pos)
+            .ok_or_else(|| StdError::parse_err("protobuf", "varint ends after the buffer"))?;
+        *pos += 1;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte < 0x80 {
+            return Ok(value);
+        }
+    }
+    Err(StdError::parse_err("protobuf", "varint is too long"))
+}
@@ -0,0 +1,532 @@
+use schemars::JsonSchema;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use std::convert::TryInto;
+
+use cosmwasm_std::{Addr, Binary, BlockInfo, Empty, Order, StdError, StdResult, Storage, Uint128};
+use cw_storage_plus::{Item, Map, U64Key};
+
+use crate::msg::{
+    Bid, CallerRestriction, ChannelInfo, DataEncoding, FailedDelivery, Limits, LotteryConfig,
+    OpRecord, PopAuctionConfig, Proposal,
+};
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Config {
+    pub owner: Addr,
+    pub max_ops_per_block: Option<u32>,
+    pub push_restriction: Option<CallerRestriction>,
+    #[serde(default)]
+    pub hidden_values: bool,
+    // unset for contracts instantiated before this was configurable
+    #[serde(default)]
+    pub capacity: Option<u32>,
+    #[serde(default)]
+    pub nois_proxy: Option<Addr>,
+    #[serde(default)]
+    pub lottery: Option<LotteryConfig>,
+    #[serde(default)]
+    pub pop_auction: Option<PopAuctionConfig>,
+    #[serde(default)]
+    pub claim_timeout: Option<u64>,
+    #[serde(default)]
+    pub data_encoding: DataEncoding,
+    #[serde(default)]
+    pub pop_type_url: Option<String>,
+    #[serde(default)]
+    pub clear_chunk_size: Option<u32>,
+    #[serde(default)]
+    pub pushers: Option<Vec<Addr>>,
+    #[serde(default)]
+    pub poppers: Option<Vec<Addr>>,
+    #[serde(default)]
+    pub pop_into_targets: Option<Vec<Addr>>,
+    #[serde(default)]
+    pub limits: Limits,
+    #[serde(default)]
+    pub cold_admin_key: Option<Binary>,
+}
+
+/// Version of the raw storage layout below, see `QueryMsg::KeyLayout`. Any
+/// change to these keys or how their values are encoded bumps it.
+pub const KEY_LAYOUT_VERSION: u32 = 2;
+
+/// JSON encoded `Config`
+pub const CONFIG_KEY: &str = "config";
+/// JSON encoded u32, the number of active items
+pub const DEPTH_KEY: &str = "depth";
+/// JSON encoded u64, the sequence number of the last operation
+pub const OP_SEQ_KEY: &str = "op_seq";
+
+/// Namespace of the items. They are stored like the entries of a
+/// `Map<U64Key, _>` of that name, but read raw since an item is either an
+/// `Item` or, in hidden values mode, a `SealedItem`.
+pub const ITEMS_NAMESPACE: &str = "items";
+
+/// Prefix of all item keys: the namespace, length prefixed like
+/// cw-storage-plus does
+pub fn items_prefix() -> Vec<u8> {
+    let namespace = ITEMS_NAMESPACE.as_bytes();
+    let mut prefix = Vec::with_capacity(namespace.len() + 10);
+    prefix.extend_from_slice(&(namespace.len() as u16).to_be_bytes());
+    prefix.extend_from_slice(namespace);
+    prefix
+}
+
+/// Raw key of the item at `index`: `items_prefix` followed by the index as
+/// 8 big-endian bytes. The value is a JSON encoded item, or sealed item in
+/// hidden values mode.
+pub fn item_storage_key(index: u64) -> Vec<u8> {
+    let mut key = items_prefix();
+    key.extend_from_slice(&index.to_be_bytes());
+    key
+}
+
+/// Index of the item stored at `key`, the inverse of `item_storage_key`
+pub fn item_index(key: &[u8]) -> u64 {
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&key[key.len() - 8..]);
+    u64::from_be_bytes(bytes)
+}
+
+/// Where the items are, so pushes don't have to scan the stack
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema, Default)]
+pub struct ItemBounds {
+    /// Index of the next push. Indexes are never reused.
+    pub tail: u64,
+    /// Number of stored items, quarantined ones included
+    pub count: u32,
+}
+
+pub const ITEM_BOUNDS: Item<ItemBounds> = Item::new("item_bounds");
+
+pub const CONFIG: Item<Config> = Item::new(CONFIG_KEY);
+
+/// Number of operations executed in the block at `height`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema, Default)]
+pub struct BlockActivity {
+    pub height: u64,
+    pub ops: u32,
+}
+
+pub const BLOCK_ACTIVITY: Item<BlockActivity> = Item::new("block_activity");
+
+/// Set when the circuit breaker tripped, cleared by the owner
+pub const PAUSED: Item<bool> = Item::new("paused");
+
+/// Indexes of items set aside by the owner. They stay in the stack but are
+/// skipped by pops and left out of aggregates.
+pub const QUARANTINED: Map<U64Key, Empty> = Map::new("quarantined");
+
+/// Ring buffer of the latest pushed values, oldest overwritten first
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct RecentValues {
+    pub capacity: u32,
+    pub values: Vec<i32>,
+    /// Slot the next value is written to once the buffer is full
+    pub next: u32,
+}
+
+impl RecentValues {
+    pub fn new(capacity: u32) -> Self {
+        RecentValues {
+            capacity,
+            values: Vec::with_capacity(capacity as usize),
+            next: 0,
+        }
+    }
+
+    pub fn push(&mut self, value: i32) {
+        if self.values.len() < self.capacity as usize {
+            self.values.push(value);
+        } else {
+            self.values[self.next as usize] = value;
+            self.next = (self.next + 1) % self.capacity;
+        }
+    }
+
+    /// The latest `n` values (or fewer, if not that many were pushed), newest first
+    pub fn latest(&self, n: usize) -> impl Iterator<Item = i32> + '_ {
+        let len = self.values.len();
+        let next = self.next as usize;
+        (0..n.min(len)).map(move |i| self.values[(next + len - 1 - i) % len])
+    }
+}
+
+pub const RECENT_VALUES: Item<RecentValues> = Item::new("recent_values");
+
+/// How many active (not quarantined) items hold each value, keyed by
+/// `value_key`. Not maintained for sealed items of a hidden values stack.
+pub const VALUE_COUNTS: Map<&[u8], u32> = Map::new("value_counts");
+
+/// Big-endian encoding of a value with the sign bit flipped, so keys sort
+/// in numeric order
+pub fn value_key(value: i32) -> [u8; 4] {
+    ((value as u32) ^ 0x8000_0000).to_be_bytes()
+}
+
+pub fn value_from_key(key: &[u8]) -> StdResult<i32> {
+    let bytes = key
+        .try_into()
+        .map_err(|_| StdError::generic_err("Corrupted value key"))?;
+    Ok((u32::from_be_bytes(bytes) ^ 0x8000_0000) as i32)
+}
+
+pub fn increment_value_count(storage: &mut dyn Storage, value: i32) -> StdResult<()> {
+    VALUE_COUNTS.update(storage, &value_key(value), |count| -> StdResult<_> {
+        Ok(count.unwrap_or_default() + 1)
+    })?;
+    update_stats(storage, |stats| {
+        stats.sum += value as i64;
+        stats.min = Some(stats.min.map_or(value, |min| min.min(value)));
+        stats.max = Some(stats.max.map_or(value, |max| max.max(value)));
+    })
+}
+
+pub fn decrement_value_count(storage: &mut dyn Storage, value: i32) -> StdResult<()> {
+    let key = value_key(value);
+    match VALUE_COUNTS.may_load(storage, &key)? {
+        Some(count) if count > 1 => VALUE_COUNTS.save(storage, &key, &(count - 1))?,
+        _ => VALUE_COUNTS.remove(storage, &key),
+    }
+    if let Some(mut stats) = STATS.may_load(storage)? {
+        stats.sum -= value as i64;
+        // the next smallest and largest values are the ends of VALUE_COUNTS
+        if stats.min == Some(value) {
+            stats.min = end_value(storage, Order::Ascending)?;
+        }
+        if stats.max == Some(value) {
+            stats.max = end_value(storage, Order::Descending)?;
+        }
+        STATS.save(storage, &stats)?;
+    }
+    Ok(())
+}
+
+fn end_value(storage: &dyn Storage, order: Order) -> StdResult<Option<i32>> {
+    VALUE_COUNTS
+        .keys(storage, None, None, order)
+        .next()
+        .map(|key| value_from_key(&key))
+        .transpose()
+}
+
+/// Running aggregates of the active items, kept up to date along with
+/// `DEPTH` and `VALUE_COUNTS`. Sum, min and max only cover integer items.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema, Default)]
+pub struct Stats {
+    pub count: u32,
+    pub sum: i64,
+    pub min: Option<i32>,
+    pub max: Option<i32>,
+}
+
+/// Unset for contracts instantiated before this was tracked, until a
+/// migration or `ExecuteMsg::Recompute` fills it in
+pub const STATS: Item<Stats> = Item::new("stats");
+
+/// Applies `action` to the stats, if they are tracked
+pub fn update_stats<A>(storage: &mut dyn Storage, action: A) -> StdResult<()>
+where
+    A: FnOnce(&mut Stats),
+{
+    let mut stats = match STATS.may_load(storage)? {
+        Some(stats) => stats,
+        None => return Ok(()),
+    };
+    action(&mut stats);
+    STATS.save(storage, &stats)
+}
+
+/// Items by the height they were pushed at, keyed by (height, index)
+pub const HEIGHT_INDEX: Map<(U64Key, U64Key), Empty> = Map::new("idx_height");
+
+/// Items by the block time they were pushed at, keyed by (nanos, index)
+pub const TIME_INDEX: Map<(U64Key, U64Key), Empty> = Map::new("idx_time");
+
+/// Number of active items in the stack
+pub const DEPTH: Item<u32> = Item::new(DEPTH_KEY);
+
+/// Snapshots of `DEPTH` at the end of each block it changed in
+pub const DEPTH_HISTORY: Map<U64Key, u32> = Map::new("depth_history");
+
+/// Depth snapshots are only kept for this many of the latest active blocks
+pub const MAX_DEPTH_SNAPSHOTS: u32 = 1000;
+
+/// Number of entries in `DEPTH_HISTORY`
+pub const DEPTH_HISTORY_LEN: Item<u32> = Item::new("depth_history_len");
+
+/// Saves the depth at the end of the block at `height`, dropping the oldest
+/// snapshot once more than `MAX_DEPTH_SNAPSHOTS` are stored.
+pub fn record_depth_snapshot(storage: &mut dyn Storage, height: u64, depth: u32) -> StdResult<()> {
+    let key = U64Key::new(height);
+    if DEPTH_HISTORY.may_load(storage, key.clone())?.is_none() {
+        make_room(
+            storage,
+            &DEPTH_HISTORY,
+            &DEPTH_HISTORY_LEN,
+            MAX_DEPTH_SNAPSHOTS,
+        )?;
+    }
+    DEPTH_HISTORY.save(storage, key, &depth)
+}
+
+/// Counts a new entry of a map that keeps at most `max` entries, removing the
+/// oldest entry instead once it is full
+fn make_room<T: Serialize + DeserializeOwned>(
+    storage: &mut dyn Storage,
+    map: &Map<U64Key, T>,
+    len: &Item<u32>,
+    max: u32,
+) -> StdResult<()> {
+    let new_len = len.may_load(storage)?.unwrap_or_default() + 1;
+    if new_len > max {
+        let oldest = map.keys(storage, None, None, Order::Ascending).next();
+        if let Some(oldest) = oldest {
+            map.remove(storage, U64Key::new(u64_from_key(&oldest)?));
+        }
+    } else {
+        len.save(storage, &new_len)?;
+    }
+    Ok(())
+}
+
+/// Push and pop counts of a single block
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema, Default)]
+pub struct BlockMetrics {
+    pub pushes: u32,
+    pub pops: u32,
+}
+
+/// Metrics are only kept for this many of the latest active blocks
+pub const MAX_METRICS_BLOCKS: u32 = 1000;
+
+pub const BLOCK_METRICS: Map<U64Key, BlockMetrics> = Map::new("block_metrics");
+/// Number of entries in `BLOCK_METRICS`
+pub const BLOCK_METRICS_LEN: Item<u32> = Item::new("block_metrics_len");
+
+/// Adds pushes and pops to the metrics of the block at `height`, dropping the
+/// oldest block once more than `MAX_METRICS_BLOCKS` are stored.
+pub fn record_block_metrics(
+    storage: &mut dyn Storage,
+    height: u64,
+    pushes: u32,
+    pops: u32,
+) -> StdResult<()> {
+    let key = U64Key::new(height);
+    let mut metrics = match BLOCK_METRICS.may_load(storage, key.clone())? {
+        Some(metrics) => metrics,
+        None => {
+            make_room(
+                storage,
+                &BLOCK_METRICS,
+                &BLOCK_METRICS_LEN,
+                MAX_METRICS_BLOCKS,
+            )?;
+            BlockMetrics::default()
+        }
+    };
+    metrics.pushes += pushes;
+    metrics.pops += pops;
+    BLOCK_METRICS.save(storage, key, &metrics)
+}
+
+/// Sequence number of the last state-changing operation. Never reset, so it
+/// survives clears and can be used to order and de-duplicate operations.
+pub const OP_SEQ: Item<u64> = Item::new(OP_SEQ_KEY);
+
+/// Bumps the global operation sequence and returns the number assigned to
+/// the operation being executed.
+pub fn next_op_seq(storage: &mut dyn Storage) -> StdResult<u64> {
+    let seq = OP_SEQ.may_load(storage)?.unwrap_or_default() + 1;
+    OP_SEQ.save(storage, &seq)?;
+    Ok(seq)
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema, Default)]
+pub struct RandomJob {
+    /// Pick items with a probability proportional to their value
+    #[serde(default)]
+    pub weighted: bool,
+}
+
+pub const CHANNELS: Map<&str, ChannelInfo> = Map::new("ibc_channels");
+
+/// The packet whose execution `ibc_packet_receive` sent last, until its reply
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PendingPacket {
+    pub channel_id: String,
+    pub sequence: u64,
+}
+
+pub const PENDING_PACKET: Item<PendingPacket> = Item::new("pending_packet");
+
+/// Failed deliveries are only kept for this many of the latest packets
+pub const MAX_FAILED_DELIVERIES: u32 = 100;
+
+pub const FAILED_DELIVERIES: Map<U64Key, FailedDelivery> = Map::new("failed_deliveries");
+/// Number of entries in `FAILED_DELIVERIES`
+pub const FAILED_DELIVERIES_LEN: Item<u32> = Item::new("failed_deliveries_len");
+/// Id of the latest failed delivery
+pub const FAILED_DELIVERY_COUNT: Item<u64> = Item::new("failed_delivery_count");
+
+/// Stores the failure of a packet under the next id, dropping the oldest one
+/// once more than `MAX_FAILED_DELIVERIES` are stored.
+pub fn record_failed_delivery(
+    storage: &mut dyn Storage,
+    block: &BlockInfo,
+    packet: PendingPacket,
+    error: String,
+    error_code: Option<u32>,
+) -> StdResult<FailedDelivery> {
+    let id = FAILED_DELIVERY_COUNT.may_load(storage)?.unwrap_or_default() + 1;
+    FAILED_DELIVERY_COUNT.save(storage, &id)?;
+    make_room(
+        storage,
+        &FAILED_DELIVERIES,
+        &FAILED_DELIVERIES_LEN,
+        MAX_FAILED_DELIVERIES,
+    )?;
+    let delivery = FailedDelivery {
+        id,
+        channel_id: packet.channel_id,
+        sequence: packet.sequence,
+        error,
+        error_code,
+        height: block.height,
+        time: block.time,
+    };
+    FAILED_DELIVERIES.save(storage, U64Key::new(id), &delivery)?;
+    Ok(delivery)
+}
+
+/// Set while a clear has items left to delete. Pushes are refused meanwhile.
+pub const CLEARING: Item<bool> = Item::new("clearing");
+
+/// A stack created with `ExecuteMsg::CreateStack`, next to the default one
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema, Default)]
+pub struct NamedStack {
+    /// Index the next push is stored under
+    pub tail: u64,
+    pub count: u32,
+}
+
+pub const STACKS: Map<&str, NamedStack> = Map::new("stacks");
+/// Items of the named stacks, by stack name and index
+pub const NAMED_ITEMS: Map<(&str, U64Key), crate::msg::Item> = Map::new("named_items");
+
+/// Ticket fees collected in lottery mode since the last payout
+pub const POT: Item<Uint128> = Item::new("pot");
+
+/// Unset until the first bid and again after the winner popped
+pub const POP_BID: Item<Bid> = Item::new("pop_bid");
+
+/// Jobs sent to the nois proxy that were not delivered yet
+pub const RANDOM_JOBS: Map<&str, RandomJob> = Map::new("random_jobs");
+
+pub const OP_LOG: Map<U64Key, OpRecord> = Map::new("op_log");
+/// Older operations are dropped from the feed
+pub const MAX_OP_LOG: u64 = 1000;
+
+/// Numbers the operation being executed like `next_op_seq` and adds it to
+/// the operations feed
+pub fn record_op(
+    storage: &mut dyn Storage,
+    block: &BlockInfo,
+    action: &str,
+    index: Option<u64>,
+    data: Option<Binary>,
+) -> StdResult<u64> {
+    save_op(storage, block, action, None, index, data)
+}
+
+/// Like `record_op` for an item of the named stack `stack`
+pub fn record_named_op(
+    storage: &mut dyn Storage,
+    block: &BlockInfo,
+    action: &str,
+    stack: &str,
+    index: u64,
+    data: Option<Binary>,
+) -> StdResult<u64> {
+    save_op(
+        storage,
+        block,
+        action,
+        Some(stack.to_string()),
+        Some(index),
+        data,
+    )
+}
+
+fn save_op(
+    storage: &mut dyn Storage,
+    block: &BlockInfo,
+    action: &str,
+    stack: Option<String>,
+    index: Option<u64>,
+    data: Option<Binary>,
+) -> StdResult<u64> {
+    let seq = next_op_seq(storage)?;
+    let record = OpRecord {
+        seq,
+        action: action.to_string(),
+        index,
+        stack,
+        data,
+        height: block.height,
+        time: block.time,
+    };
+    OP_LOG.save(storage, U64Key::new(seq), &record)?;
+    if seq > MAX_OP_LOG {
+        OP_LOG.remove(storage, U64Key::new(seq - MAX_OP_LOG));
+    }
+    Ok(seq)
+}
+
+/// Members sharing the owner rights. When set, the owner is the contract
+/// itself and owner messages only run through executed proposals.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Council {
+    pub members: Vec<Member>,
+    pub threshold: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Member {
+    pub addr: Addr,
+    pub weight: u64,
+}
+
+impl Council {
+    /// Voting weight of `addr`, or None if it is not a member
+    pub fn weight(&self, addr: &Addr) -> Option<u64> {
+        self.members
+            .iter()
+            .find(|member| member.addr == *addr)
+            .map(|member| member.weight)
+    }
+}
+
+pub const COUNCIL: Item<Council> = Item::new("council");
+
+pub const PROPOSALS: Map<U64Key, Proposal> = Map::new("proposals");
+/// Number of proposals made so far, the id of the latest one
+pub const PROPOSAL_COUNT: Item<u64> = Item::new("proposal_count");
+/// Members that voted on each proposal, keyed by (proposal id, member)
+pub const VOTES: Map<(U64Key, &Addr), Empty> = Map::new("votes");
+
+/// Nonce of the last `ExecuteMsg::AdminOpWithSig`, unset before the first
+pub const ADMIN_NONCE: Item<u64> = Item::new("admin_nonce");
+/// Set while the op of an `ExecuteMsg::AdminOpWithSig` executes, so the
+/// messages reserved to the cold admin key know they were signed
+pub const SIGNED_OP: Item<bool> = Item::new("signed_op");
+
+/// Decodes the raw key of a `U64Key` map as returned by `keys` and `range`
+pub fn u64_from_key(key: &[u8]) -> StdResult<u64> {
+    let bytes = key
+        .try_into()
+        .map_err(|_| StdError::generic_err("Corrupted u64 key"))?;
+    Ok(u64::from_be_bytes(bytes))
+}